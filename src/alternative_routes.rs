@@ -0,0 +1,245 @@
+use crate::road_network::{Cost, RoadNetwork, Node, NodeIndex};
+use crate::shortest_path::{bounded_shortest_path_tree, dijkstra_shortest_path, Direction, ShortestPath};
+
+use std::collections::{HashMap, HashSet};
+
+
+/// Limits on how an alternative route may compare to the optimal route.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AlternativeRouteParameters {
+    /// The most alternatives to return.
+    pub max_alternatives: usize,
+    /// How much longer than the optimal route an alternative may be, as a fraction of its cost.
+    pub max_stretch: f64,
+    /// The largest fraction of the optimal route's cost an alternative may share with it,
+    /// or with any alternative chosen before it.
+    pub max_sharing: f64,
+    /// The shortest plateau an alternative must contain, as a fraction of the optimal
+    /// route's cost. Every part of the alternative this long is itself a shortest path.
+    pub min_local_optimality: f64,
+}
+
+
+impl Default for AlternativeRouteParameters {
+    fn default() -> Self {
+        AlternativeRouteParameters {
+            max_alternatives: 3,
+            max_stretch: 0.25,
+            max_sharing: 0.8,
+            min_local_optimality: 0.25,
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub struct AlternativeRoute {
+    pub route: ShortestPath,
+    /// The percentage of the optimal route's cost which this route shares with it.
+    pub overlap: f64,
+}
+
+
+/// Find alternatives to the optimal route with the plateau method.
+///
+/// A plateau is a chain of edges which lies in both the forward shortest path tree from the
+/// start and the backward shortest path tree from the end. Following the forward tree to a
+/// plateau, along it and then the backward tree to the end gives a candidate route, which
+/// is kept if it passes the filters in the parameters. Candidates with longer plateaus are
+/// preferred.
+pub fn alternative_routes(network: &RoadNetwork,
+                          start_node: &Node,
+                          end_node: &Node,
+                          parameters: &AlternativeRouteParameters,
+) -> Vec<AlternativeRoute> {
+    let optimal = match dijkstra_shortest_path(network, start_node, end_node) {
+        Some(optimal) => optimal,
+        None => return Vec::new(),
+    };
+    if optimal.cost == 0 {
+        return Vec::new();
+    }
+
+    let max_cost = (optimal.cost as f64 * (1. + parameters.max_stretch)) as Cost;
+    let forward = bounded_shortest_path_tree(network, start_node.id, Direction::Forward, max_cost);
+    let backward = bounded_shortest_path_tree(network, end_node.id, Direction::Backward, max_cost);
+    let mut statistics = optimal.statistics.unwrap_or_default();
+    statistics.add(forward.statistics());
+    statistics.add(backward.statistics());
+
+    let is_plateau_edge = |from: NodeIndex, to: NodeIndex| {
+        forward.parents.get(&to) == Some(&Some(from)) && backward.parents.get(&from) == Some(&Some(to))
+    };
+
+    let mut plateaus = Vec::new();
+    for (node_index, forward_cost) in forward.costs.iter() {
+        let is_plateau_start = match backward.parents.get(node_index) {
+            Some(Some(next)) => is_plateau_edge(*node_index, *next),
+            _ => false,
+        };
+        let continues_plateau = match forward.parents.get(node_index) {
+            Some(Some(previous)) => is_plateau_edge(*previous, *node_index),
+            _ => false,
+        };
+        if !is_plateau_start || continues_plateau {
+            continue;
+        }
+
+        let mut plateau_end = *node_index;
+        while let Some(Some(next)) = backward.parents.get(&plateau_end) {
+            if !is_plateau_edge(plateau_end, *next) {
+                break;
+            }
+            plateau_end = *next;
+        }
+
+        let plateau_cost = forward.costs[&plateau_end] - forward_cost;
+        let cost = forward.costs[&plateau_end] + backward.costs[&plateau_end];
+        if cost <= max_cost && plateau_cost as f64 >= parameters.min_local_optimality * optimal.cost as f64 {
+            plateaus.push((plateau_cost, cost, *node_index));
+        }
+    }
+    plateaus.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+
+    let optimal_edges = path_edges(&optimal.path);
+    let mut chosen_edges = vec![optimal_edges.clone()];
+    let mut alternatives = Vec::new();
+
+    for (_, cost, plateau_start) in plateaus {
+        if alternatives.len() >= parameters.max_alternatives {
+            break;
+        }
+
+        let mut path = trace_tree(&forward.parents, plateau_start);
+        path.reverse();
+        let mut plateau_and_rest = trace_tree(&backward.parents, plateau_start);
+        plateau_and_rest.remove(0);
+        path.extend(plateau_and_rest);
+
+        let unique_nodes: HashSet<_> = path.iter().collect();
+        if unique_nodes.len() != path.len() {
+            continue;
+        }
+
+        let edges = path_edges(&path);
+        let shares_too_much = chosen_edges.iter().any(|other_edges| {
+            shared_cost(network, &edges, other_edges) as f64 > parameters.max_sharing * optimal.cost as f64
+        });
+        if shares_too_much {
+            continue;
+        }
+
+        let overlap = 100. * shared_cost(network, &edges, &optimal_edges) as f64 / optimal.cost as f64;
+        chosen_edges.push(edges);
+        alternatives.push(AlternativeRoute {route: ShortestPath {cost, path, statistics: Some(statistics)}, overlap});
+    }
+
+    alternatives
+}
+
+
+/// The nodes from a node to the root of a shortest path tree.
+fn trace_tree(parents: &HashMap<NodeIndex, Option<NodeIndex>>, node_index: NodeIndex) -> Vec<NodeIndex> {
+    let mut path = vec![node_index];
+    let mut current = node_index;
+    while let Some(Some(parent)) = parents.get(&current) {
+        path.push(*parent);
+        current = *parent;
+    }
+    path
+}
+
+
+fn path_edges(path: &[NodeIndex]) -> HashSet<(NodeIndex, NodeIndex)> {
+    path.iter().cloned().zip(path.iter().cloned().skip(1)).collect()
+}
+
+
+fn shared_cost(network: &RoadNetwork,
+               edges: &HashSet<(NodeIndex, NodeIndex)>,
+               other_edges: &HashSet<(NodeIndex, NodeIndex)>) -> Cost {
+    edges.intersection(other_edges)
+        .map(|&(from, to)| network.edge_cost(from, to).unwrap())
+        .sum()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::alternative_routes::*;
+    use crate::geo_utils::Location;
+
+
+    /// Two routes from 1 to 8 around either side of a block, and a short cut across it.
+    fn get_test_network() -> RoadNetwork {
+        let mut network = RoadNetwork::new();
+        for i in 1..9 {
+            network.add_node(Node::new(i, Location::new(0., 0.))).unwrap();
+        }
+
+        let mut add_road = |from, to, cost| {
+            network.add_edge(from, to, cost);
+            network.add_edge(to, from, cost);
+        };
+        add_road(1, 2, 10);
+        add_road(2, 3, 10);
+        add_road(3, 4, 10);
+        add_road(4, 8, 10);
+        add_road(1, 5, 11);
+        add_road(5, 6, 11);
+        add_road(6, 7, 11);
+        add_road(7, 8, 11);
+        add_road(3, 6, 30);
+
+        network
+    }
+
+
+    #[test]
+    fn test_finds_route_around_other_side() {
+        let network = get_test_network();
+        let start = network.get_node(1).unwrap();
+        let end = network.get_node(8).unwrap();
+
+        let alternatives = alternative_routes(&network, start, end, &AlternativeRouteParameters::default());
+        assert_eq!(1, alternatives.len());
+        assert_eq!(vec![1, 5, 6, 7, 8], alternatives[0].route.path);
+        assert_eq!(44, alternatives[0].route.cost);
+        assert_eq!(0., alternatives[0].overlap);
+
+        // The optimal route's search and both trees, which reach all eight nodes each.
+        let statistics = alternatives[0].route.statistics().unwrap();
+        assert!(statistics.settled_nodes > 16, "{:?}", statistics);
+    }
+
+
+    #[test]
+    fn test_stretch_limits_alternatives() {
+        let network = get_test_network();
+        let start = network.get_node(1).unwrap();
+        let end = network.get_node(8).unwrap();
+
+        let parameters = AlternativeRouteParameters {max_stretch: 0.05, ..Default::default()};
+        assert!(alternative_routes(&network, start, end, &parameters).is_empty());
+    }
+
+
+    #[test]
+    fn test_sharing_limits_alternatives() {
+        let network = get_test_network();
+        let start = network.get_node(2).unwrap();
+        let end = network.get_node(8).unwrap();
+
+        let parameters = AlternativeRouteParameters {
+            max_alternatives: 10,
+            max_stretch: 3.,
+            max_sharing: 0.2,
+            min_local_optimality: 0.,
+        };
+        let alternatives = alternative_routes(&network, start, end, &parameters);
+        assert!(!alternatives.is_empty());
+        for alternative in alternatives {
+            assert!(alternative.overlap <= 20.);
+        }
+    }
+}
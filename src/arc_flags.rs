@@ -0,0 +1,301 @@
+use crate::partition::{num_cells_at_depth, recursive_bisection, sort_along_widest_dimension, InertialFlowParameters};
+use crate::potential::ZeroPotential;
+use crate::road_network::{RoadNetwork, Node, NodeIndex};
+use crate::shortest_path::{filtered_shortest_path, shortest_path_tree, Direction, ShortestPath};
+
+use std::collections::HashMap;
+use std::error::Error;
+
+
+/// Ways of dividing a network into regions by the location of its nodes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Partitioning {
+    /// Split the bounding box of the network into equally sized cells. Zero rows or
+    /// columns are treated as one.
+    Grid { rows: usize, cols: usize },
+    /// Repeatedly split regions in half at the median of their widest dimension,
+    /// giving `2^depth` regions with roughly equal numbers of nodes. The depth may not
+    /// give more regions than the network has nodes.
+    KdTree { depth: usize },
+    /// Repeatedly bisect regions with inertial flow, giving `2^depth` regions with few
+    /// roads between them. The same limit on the depth applies.
+    InertialFlow { depth: usize },
+}
+
+
+/// A set of regions stored as a bitset.
+#[derive(Clone, Debug)]
+struct RegionFlags {
+    words: Vec<u64>,
+}
+
+
+impl RegionFlags {
+    fn new(num_regions: usize) -> Self {
+        RegionFlags {words: vec![0; num_regions.div_ceil(64)]}
+    }
+
+    fn set(&mut self, region: usize) {
+        self.words[region / 64] |= 1 << (region % 64);
+    }
+
+    fn get(&self, region: usize) -> bool {
+        self.words[region / 64] & (1 << (region % 64)) != 0
+    }
+}
+
+
+/// For every edge, the set of regions it lies on a shortest path into.
+pub struct ArcFlags {
+    regions: HashMap<NodeIndex, usize>,
+    num_regions: usize,
+    /// The flags of each node's edges, in the same order as its `neighbours`.
+    flags: HashMap<NodeIndex, Vec<RegionFlags>>,
+}
+
+
+impl ArcFlags {
+
+    /// Partition the network into regions and compute the flags of every edge.
+    ///
+    /// Every edge is flagged for the region it lies within, and for every region it
+    /// lies on a shortest path into, found by a backward search from each node where
+    /// an edge enters the region. Returns an error if the partitioning asks for too
+    /// many regions.
+    pub fn new(network: &RoadNetwork, partitioning: Partitioning) -> Result<Self, Box<dyn Error>> {
+        let (regions, num_regions) = partition(network, partitioning)?;
+
+        let mut flags: HashMap<NodeIndex, Vec<RegionFlags>> = network.nodes_iter()
+            .map(|(node_index, node)| (*node_index, vec![RegionFlags::new(num_regions); node.out_degree()]))
+            .collect();
+
+        for (node_index, node) in network.nodes_iter() {
+            let region = regions[node_index];
+            for (edge_index, edge) in node.neighbours.iter().enumerate() {
+                if regions[&edge.destination] == region {
+                    flags.get_mut(node_index).unwrap()[edge_index].set(region);
+                }
+            }
+        }
+
+        for (node_index, node) in network.nodes_iter() {
+            let region = regions[node_index];
+            let is_boundary = node.reverse_neighbours
+                .iter()
+                .any(|edge| regions[&edge.origin] != region);
+            if !is_boundary {
+                continue;
+            }
+
+            // Flag every edge on any shortest path to the boundary node, not just those
+            // in the tree, so that ties can't leave a region unreachable.
+            let costs = shortest_path_tree(network, *node_index, Direction::Backward).costs;
+            for (from, from_cost) in costs.iter() {
+                let from_node = network.get_node(*from).unwrap();
+                for (edge_index, edge) in from_node.neighbours.iter().enumerate() {
+                    if let Some(to_cost) = costs.get(&edge.destination) {
+                        if *from_cost == to_cost + edge.cost {
+                            flags.get_mut(from).unwrap()[edge_index].set(region);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(ArcFlags {regions, num_regions, flags})
+    }
+
+
+    pub fn num_regions(&self) -> usize {
+        self.num_regions
+    }
+
+
+    pub fn region(&self, node_index: NodeIndex) -> Option<usize> {
+        self.regions.get(&node_index).cloned()
+    }
+
+
+    /// Whether the edge at `edge_index` of a node's `neighbours` is flagged for a region.
+    pub fn is_flagged(&self, node_index: NodeIndex, edge_index: usize, region: usize) -> bool {
+        self.flags.get(&node_index)
+            .and_then(|flags| flags.get(edge_index))
+            .is_some_and(|flags| flags.get(region))
+    }
+}
+
+
+/// Dijkstra's algorithm only following edges flagged for the region of the end node.
+pub fn arc_flags_shortest_path(network: &RoadNetwork,
+                               arc_flags: &ArcFlags,
+                               start_node: &Node,
+                               end_node: &Node,
+) -> Option<ShortestPath> {
+    let region = arc_flags.region(end_node.id)?;
+    filtered_shortest_path(network, start_node, end_node, &mut ZeroPotential, |node, edge_index| {
+        arc_flags.is_flagged(node.id, edge_index, region)
+    })
+}
+
+
+fn partition(network: &RoadNetwork,
+             partitioning: Partitioning) -> Result<(HashMap<NodeIndex, usize>, usize), Box<dyn Error>> {
+    let mut nodes: Vec<_> = network.nodes_iter()
+        .map(|(node_index, node)| (*node_index, node.location.lat(), node.location.lng()))
+        .collect();
+    nodes.sort_by_key(|&(node_index, _, _)| node_index);
+
+    match partitioning {
+        Partitioning::Grid {rows, cols} => {
+            let (rows, cols) = (rows.max(1), cols.max(1));
+            let num_regions = rows.checked_mul(cols)
+                .ok_or_else(|| format!("Too many regions in a grid of {} by {}", rows, cols))?;
+            Ok((grid_partition(&nodes, rows, cols), num_regions))
+        },
+        Partitioning::KdTree {depth} => {
+            let num_regions = num_cells_at_depth(depth, nodes.len())?;
+            let mut regions = HashMap::with_capacity(nodes.len());
+            kd_partition(&mut nodes, depth, 0, &mut regions);
+            Ok((regions, num_regions))
+        },
+        Partitioning::InertialFlow {depth} => {
+            let num_regions = num_cells_at_depth(depth, nodes.len())?;
+            Ok((recursive_bisection(network, depth, &InertialFlowParameters::default())?, num_regions))
+        },
+    }
+}
+
+
+fn grid_partition(nodes: &[(NodeIndex, f64, f64)], rows: usize, cols: usize) -> HashMap<NodeIndex, usize> {
+    let min_lat = nodes.iter().map(|node| node.1).fold(f64::INFINITY, f64::min);
+    let max_lat = nodes.iter().map(|node| node.1).fold(f64::NEG_INFINITY, f64::max);
+    let min_lng = nodes.iter().map(|node| node.2).fold(f64::INFINITY, f64::min);
+    let max_lng = nodes.iter().map(|node| node.2).fold(f64::NEG_INFINITY, f64::max);
+
+    let cell = |value: f64, min: f64, max: f64, num_cells: usize| {
+        if max <= min {
+            return 0;
+        }
+        let cell = ((value - min) / (max - min) * num_cells as f64) as usize;
+        cell.min(num_cells - 1)
+    };
+
+    nodes.iter()
+        .map(|&(node_index, lat, lng)| {
+            let row = cell(lat, min_lat, max_lat, rows);
+            let col = cell(lng, min_lng, max_lng, cols);
+            (node_index, row * cols + col)
+        })
+        .collect()
+}
+
+
+fn kd_partition(nodes: &mut [(NodeIndex, f64, f64)],
+                depth: usize,
+                region: usize,
+                regions: &mut HashMap<NodeIndex, usize>) {
+    if depth == 0 {
+        for &(node_index, _, _) in nodes.iter() {
+            regions.insert(node_index, region);
+        }
+        return;
+    }
+
+    sort_along_widest_dimension(nodes);
+    let (lower, upper) = nodes.split_at_mut(nodes.len() / 2);
+    kd_partition(lower, depth - 1, 2 * region, regions);
+    kd_partition(upper, depth - 1, 2 * region + 1, regions);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::arc_flags::*;
+    use crate::shortest_path::dijkstra_shortest_path;
+    use crate::test_utils::build_grid_network;
+
+
+    fn check_matches_dijkstra(network: &RoadNetwork, arc_flags: &ArcFlags) {
+        for (_, start) in network.nodes_iter() {
+            for (_, end) in network.nodes_iter() {
+                let dijkstra = dijkstra_shortest_path(network, start, end).unwrap();
+                let flagged = arc_flags_shortest_path(network, arc_flags, start, end).unwrap();
+                assert_eq!(dijkstra.cost, flagged.cost);
+            }
+        }
+    }
+
+
+    #[test]
+    fn test_grid_partitioning() {
+        let network = build_grid_network(6, 6);
+        let arc_flags = ArcFlags::new(&network, Partitioning::Grid {rows: 2, cols: 3}).unwrap();
+
+        assert_eq!(6, arc_flags.num_regions());
+        assert_eq!(Some(0), arc_flags.region(0));
+        assert_eq!(Some(5), arc_flags.region(35));
+        check_matches_dijkstra(&network, &arc_flags);
+    }
+
+
+    #[test]
+    fn test_empty_grid_partitioning() {
+        let network = build_grid_network(3, 3);
+        let arc_flags = ArcFlags::new(&network, Partitioning::Grid {rows: 0, cols: 2}).unwrap();
+
+        assert_eq!(2, arc_flags.num_regions());
+        check_matches_dijkstra(&network, &arc_flags);
+    }
+
+
+    #[test]
+    fn test_kd_tree_partitioning() {
+        let network = build_grid_network(6, 6);
+        let arc_flags = ArcFlags::new(&network, Partitioning::KdTree {depth: 2}).unwrap();
+
+        assert_eq!(4, arc_flags.num_regions());
+        for region in 0..4 {
+            let size = (0..36).filter(|node_index| arc_flags.region(*node_index) == Some(region)).count();
+            assert_eq!(9, size);
+        }
+        check_matches_dijkstra(&network, &arc_flags);
+    }
+
+
+    #[test]
+    fn test_more_regions_than_nodes() {
+        let network = build_grid_network(6, 6);
+        assert_eq!(32, ArcFlags::new(&network, Partitioning::KdTree {depth: 5}).unwrap().num_regions());
+        assert!(ArcFlags::new(&network, Partitioning::KdTree {depth: 6}).is_err());
+        assert!(ArcFlags::new(&network, Partitioning::KdTree {depth: 64}).is_err());
+        assert!(ArcFlags::new(&network, Partitioning::InertialFlow {depth: 6}).is_err());
+        assert!(ArcFlags::new(&network, Partitioning::Grid {rows: usize::MAX, cols: 2}).is_err());
+    }
+
+
+    #[test]
+    fn test_inertial_flow_partitioning() {
+        let network = build_grid_network(6, 6);
+        let arc_flags = ArcFlags::new(&network, Partitioning::InertialFlow {depth: 2}).unwrap();
+
+        assert_eq!(4, arc_flags.num_regions());
+        assert!((0..36).all(|node_index| arc_flags.region(node_index).is_some()));
+        check_matches_dijkstra(&network, &arc_flags);
+    }
+
+
+    #[test]
+    fn test_edges_within_a_region_are_flagged() {
+        let network = build_grid_network(4, 4);
+        let arc_flags = ArcFlags::new(&network, Partitioning::Grid {rows: 2, cols: 2}).unwrap();
+
+        for (node_index, node) in network.nodes_iter() {
+            let region = arc_flags.region(*node_index).unwrap();
+            for (edge_index, edge) in node.neighbours.iter().enumerate() {
+                if arc_flags.region(edge.destination) == Some(region) {
+                    assert!(arc_flags.is_flagged(*node_index, edge_index, region));
+                }
+            }
+        }
+    }
+}
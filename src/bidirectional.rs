@@ -0,0 +1,275 @@
+use crate::road_network::{Cost, RoadNetwork, Node, NodeIndex};
+use crate::potential::{GeometricPotential, Potential, ZeroPotential};
+use crate::shortest_path::{edges, Direction, SearchStatistics, ShortestPath};
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::time::Instant;
+
+
+/// Dijkstra's algorithm searching forwards from the start and backwards from the end
+/// at the same time, stopping once the two searches can no longer improve on the best
+/// path where they have met.
+pub fn bidirectional_dijkstra_shortest_path(network: &RoadNetwork,
+                                            start_node: &Node,
+                                            end_node: &Node,
+) -> Option<ShortestPath> {
+    bidirectional_shortest_path(network, start_node, end_node, &mut ZeroPotential, &mut ZeroPotential)
+}
+
+
+/// Bidirectional A* using the haversine potential of `astar_shortest_path`.
+pub fn bidirectional_astar_shortest_path(network: &RoadNetwork,
+                                         start_node: &Node,
+                                         end_node: &Node,
+) -> Option<ShortestPath> {
+    bidirectional_shortest_path(network, start_node, end_node,
+                                &mut GeometricPotential::default(),
+                                &mut GeometricPotential::default())
+}
+
+
+/// Bidirectional search with averaged potentials.
+///
+/// The forward search uses `(to_end(v) - to_start(v)) / 2` and the backward search its
+/// negation, so both searches work on the same reduced costs and the usual stopping
+/// criterion stays correct. `to_end` must bound the cost from each node to the end and
+/// `to_start` the cost from the start to each node, as a symmetric distance does.
+///
+/// Keys are doubled to keep the halved potentials in integers.
+pub fn bidirectional_shortest_path<P, Q>(network: &RoadNetwork,
+                                         start_node: &Node,
+                                         end_node: &Node,
+                                         to_end: &mut P,
+                                         to_start: &mut Q,
+) -> Option<ShortestPath>
+    where P: Potential,
+          Q: Potential
+{
+    let start_time = Instant::now();
+    to_end.init(end_node);
+    to_start.init(start_node);
+    let forward_potential = |node: &Node| {
+        to_end.potential(node) as i64 - to_start.potential(node) as i64
+    };
+
+    let mut forward = HalfSearch::new(Direction::Forward, start_node, forward_potential(start_node));
+    let mut backward = HalfSearch::new(Direction::Backward, end_node, -forward_potential(end_node));
+    let mut best: Option<(Cost, NodeIndex)> = None;
+
+    while let (Some(forward_key), Some(backward_key)) = (forward.min_key(), backward.min_key()) {
+        if let Some((best_cost, _)) = best {
+            if forward_key + backward_key >= 2 * best_cost as i64 {
+                break;
+            }
+        }
+
+        let (search, other, sign) = if forward_key <= backward_key {
+            (&mut forward, &backward, 1)
+        } else {
+            (&mut backward, &forward, -1)
+        };
+
+        for (node_index, cost) in search.step(network, |node| sign * forward_potential(node)) {
+            if let Some(other_cost) = other.costs.get(&node_index) {
+                let total = cost + other_cost;
+                if best.is_none_or(|(best_cost, _)| total < best_cost) {
+                    best = Some((total, node_index));
+                }
+            }
+        }
+    }
+
+    let (cost, meeting_node) = best?;
+
+    let mut path = forward.path_to(meeting_node);
+    let mut backward_path = backward.path_to(meeting_node);
+    backward_path.pop();
+    backward_path.reverse();
+    path.extend(backward_path);
+
+    let mut statistics = forward.statistics;
+    statistics.add(&backward.statistics);
+    statistics.duration = start_time.elapsed();
+
+    Some(ShortestPath {cost, path, statistics: Some(statistics)})
+}
+
+
+struct HalfSearch {
+    direction: Direction,
+    heap: BinaryHeap<Reverse<(i64, NodeIndex)>>,
+    costs: HashMap<NodeIndex, Cost>,
+    parents: HashMap<NodeIndex, NodeIndex>,
+    settled: HashSet<NodeIndex>,
+    statistics: SearchStatistics,
+}
+
+
+impl HalfSearch {
+
+    fn new(direction: Direction, source: &Node, source_potential: i64) -> Self {
+        let mut search = HalfSearch {
+            direction,
+            heap: BinaryHeap::new(),
+            costs: HashMap::new(),
+            parents: HashMap::new(),
+            settled: HashSet::new(),
+            statistics: SearchStatistics::default(),
+        };
+        search.costs.insert(source.id, 0);
+        search.heap.push(Reverse((source_potential, source.id)));
+        search.statistics.record_push(search.heap.len());
+        search
+    }
+
+
+    fn min_key(&mut self) -> Option<i64> {
+        while let Some(&Reverse((key, node_index))) = self.heap.peek() {
+            if !self.settled.contains(&node_index) {
+                return Some(key);
+            }
+            self.heap.pop();
+        }
+        None
+    }
+
+
+    /// Settle the next node and relax its edges, returning every node whose cost
+    /// was set along with the new cost.
+    ///
+    /// Debug builds check the doubled potential is consistent on every relaxed edge.
+    fn step<P>(&mut self, network: &RoadNetwork, potential: P) -> Vec<(NodeIndex, Cost)>
+        where P: Fn(&Node) -> i64
+    {
+        let node_index = match self.heap.pop() {
+            Some(Reverse((_, node_index))) => node_index,
+            None => return Vec::new(),
+        };
+        self.settled.insert(node_index);
+        self.statistics.settled_nodes += 1;
+
+        let cost = self.costs[&node_index];
+        let mut updated = vec![(node_index, cost)];
+
+        let node = network.get_node(node_index).unwrap();
+        for (neighbour, edge_cost) in edges(node, self.direction) {
+            let neighbour_cost = cost + edge_cost;
+            let neighbour_node = network.get_node(neighbour).unwrap();
+            self.statistics.relaxed_edges += 1;
+
+            debug_assert!(potential(node) <= 2 * edge_cost as i64 + potential(neighbour_node),
+                          "Inconsistent potential on the edge from {} to {}: {} > 2 * {} + {}",
+                          if self.direction == Direction::Forward { node_index } else { neighbour },
+                          if self.direction == Direction::Forward { neighbour } else { node_index },
+                          potential(node), edge_cost, potential(neighbour_node));
+
+            if self.costs.get(&neighbour).is_none_or(|current| neighbour_cost < *current) {
+                self.costs.insert(neighbour, neighbour_cost);
+                self.parents.insert(neighbour, node_index);
+
+                let key = 2 * neighbour_cost as i64 + potential(neighbour_node);
+                self.heap.push(Reverse((key, neighbour)));
+                self.statistics.record_push(self.heap.len());
+                updated.push((neighbour, neighbour_cost));
+            }
+        }
+        updated
+    }
+
+
+    /// The nodes from the source of the search to the given node.
+    fn path_to(&self, node_index: NodeIndex) -> Vec<NodeIndex> {
+        let mut path = vec![node_index];
+        let mut current = node_index;
+        while let Some(parent) = self.parents.get(&current) {
+            path.push(*parent);
+            current = *parent;
+        }
+        path.reverse();
+        path
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::bidirectional::*;
+    use crate::geo_utils::Location;
+    use crate::shortest_path::dijkstra_shortest_path;
+    use crate::test_utils::build_grid_network;
+
+
+    fn check_matches_dijkstra(network: &RoadNetwork) {
+        for (start_index, start) in network.nodes_iter() {
+            for (end_index, end) in network.nodes_iter() {
+                let dijkstra = dijkstra_shortest_path(network, start, end).map(|path| path.cost);
+
+                for result in [bidirectional_dijkstra_shortest_path(network, start, end),
+                               bidirectional_astar_shortest_path(network, start, end)] {
+                    assert_eq!(dijkstra, result.as_ref().map(|path| path.cost));
+                    if let Some(result) = result {
+                        assert_eq!(Some(start_index), result.path.first());
+                        assert_eq!(Some(end_index), result.path.last());
+                    }
+                }
+            }
+        }
+    }
+
+
+    #[test]
+    fn test_grid_network() {
+        check_matches_dijkstra(&build_grid_network(6, 6));
+    }
+
+
+    #[test]
+    fn test_network_with_one_way_streets() {
+        let mut network = RoadNetwork::new();
+        for i in 1..6 {
+            network.add_node(Node::new(i, Location::new(0., 0.))).unwrap();
+        }
+
+        network.add_edge(1, 2, 5);
+        network.add_edge(2, 3, 10);
+        network.add_edge(3, 4, 20);
+        network.add_edge(1, 4, 100);
+
+        check_matches_dijkstra(&network);
+
+        let start = network.get_node(1).unwrap();
+        let end = network.get_node(4).unwrap();
+        let result = bidirectional_dijkstra_shortest_path(&network, start, end).unwrap();
+        assert_eq!(vec![1, 2, 3, 4], result.path);
+    }
+
+
+    /// Claims the start node is much further from the end than it is.
+    struct InconsistentPotential;
+
+    impl Potential for InconsistentPotential {
+        fn potential(&self, node: &Node) -> Cost {
+            if node.id == 1 { 1000 } else { 0 }
+        }
+    }
+
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Inconsistent potential on the edge from 1 to 4")]
+    fn test_inconsistent_potential_is_detected() {
+        let mut network = RoadNetwork::new();
+        for i in 1..5 {
+            network.add_node(Node::new(i, Location::new(0., 0.))).unwrap();
+        }
+        network.add_edge(1, 2, 5);
+        network.add_edge(2, 3, 10);
+        network.add_edge(3, 4, 20);
+        network.add_edge(1, 4, 100);
+
+        let start = network.get_node(1).unwrap();
+        let end = network.get_node(4).unwrap();
+        bidirectional_shortest_path(&network, start, end, &mut InconsistentPotential, &mut ZeroPotential);
+    }
+}
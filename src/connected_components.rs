@@ -42,7 +42,7 @@ fn nodes_in_order(network: &RoadNetwork) -> Vec<NodeIndex> {
     }
 
     state.result.reverse();
-    return state.result;
+    state.result
 }
 
 
@@ -70,8 +70,7 @@ fn build_component(network: &RoadNetwork,
     }
 
     let mut stack = vec![root];
-    while stack.len() > 0 {
-        let node_index = stack.pop().unwrap();
+    while let Some(node_index) = stack.pop() {
         assigned_nodes.insert(node_index);
         component.push(node_index);
 
@@ -90,9 +89,9 @@ fn build_component(network: &RoadNetwork,
 
 #[cfg(test)]
 mod tests {
-    use connected_components::*;
-    use road_network::{Node, RoadNetwork};
-    use geo_utils::Location;
+    use crate::connected_components::*;
+    use crate::road_network::{Node, RoadNetwork};
+    use crate::geo_utils::Location;
 
 
     fn build_triangle_network() -> RoadNetwork {
@@ -0,0 +1,564 @@
+use crate::matrix::CostMatrix;
+use crate::road_network::{Cost, RoadNetwork, Node, NodeIndex};
+use crate::shortest_path::{SearchStatistics, ShortestPath};
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::time::Instant;
+
+
+/// Give up looking for a witness path after settling this many nodes.
+///
+/// Stopping early can only add shortcuts that were not needed, never lose a path.
+const MAX_WITNESS_SETTLED_NODES: usize = 500;
+
+
+#[derive(Clone, Copy, Debug)]
+struct HierarchyEdge {
+    node: NodeIndex,
+    cost: Cost,
+}
+
+
+/// Cost and middle node of an edge while the graph is being contracted.
+/// Edges of the original network have no middle node.
+type ContractionEdges = HashMap<NodeIndex, (Cost, Option<NodeIndex>)>;
+
+
+/// A road network preprocessed by contracting every node in order of importance.
+///
+/// The hierarchy only keeps the edges leading to more important nodes, so a query
+/// only has to search upwards from both ends of the route.
+pub struct ContractionHierarchy {
+    ranks: HashMap<NodeIndex, usize>,
+    /// For each node, the edges to more important nodes.
+    upward_edges: HashMap<NodeIndex, Vec<HierarchyEdge>>,
+    /// For each node, the edges from more important nodes, stored reversed.
+    downward_edges: HashMap<NodeIndex, Vec<HierarchyEdge>>,
+    /// The node a shortcut skips over, or None for an edge of the original network.
+    middle_nodes: HashMap<(NodeIndex, NodeIndex), Option<NodeIndex>>,
+}
+
+
+impl ContractionHierarchy {
+
+    /// Order the nodes of the network and contract them, adding shortcuts wherever
+    /// removing a node would lengthen a shortest path.
+    pub fn new(network: &RoadNetwork) -> Self {
+        Contraction::new(network).run()
+    }
+
+
+    /// The position of a node in the contraction order, more important nodes have a higher rank.
+    pub fn rank(&self, node_index: NodeIndex) -> Option<usize> {
+        self.ranks.get(&node_index).cloned()
+    }
+
+
+    pub fn num_shortcuts(&self) -> usize {
+        self.middle_nodes.values().filter(|middle| middle.is_some()).count()
+    }
+
+
+    fn unpack_edge(&self, from: NodeIndex, to: NodeIndex, path: &mut Vec<NodeIndex>) {
+        match self.middle_nodes[&(from, to)] {
+            Some(middle) => {
+                self.unpack_edge(from, middle, path);
+                self.unpack_edge(middle, to, path);
+            },
+            None => path.push(to),
+        }
+    }
+}
+
+
+struct Contraction<'a> {
+    network: &'a RoadNetwork,
+    out_edges: HashMap<NodeIndex, ContractionEdges>,
+    in_edges: HashMap<NodeIndex, ContractionEdges>,
+    deleted_neighbours: HashMap<NodeIndex, i64>,
+    hierarchy: ContractionHierarchy,
+}
+
+
+struct Shortcut {
+    from: NodeIndex,
+    to: NodeIndex,
+    cost: Cost,
+}
+
+
+impl<'a> Contraction<'a> {
+
+    fn new(network: &'a RoadNetwork) -> Self {
+        let mut out_edges: HashMap<_, ContractionEdges> = HashMap::with_capacity(network.num_nodes());
+        let mut in_edges: HashMap<_, ContractionEdges> = HashMap::with_capacity(network.num_nodes());
+
+        for (node_index, node) in network.nodes_iter() {
+            out_edges.entry(*node_index).or_default();
+            in_edges.entry(*node_index).or_default();
+            for edge in node.neighbours.iter().filter(|edge| edge.destination != *node_index) {
+                insert_min_edge(out_edges.get_mut(node_index).unwrap(), edge.destination, edge.cost, None);
+                insert_min_edge(in_edges.entry(edge.destination).or_default(), *node_index, edge.cost, None);
+            }
+        }
+
+        Contraction {
+            network,
+            out_edges,
+            in_edges,
+            deleted_neighbours: HashMap::with_capacity(network.num_nodes()),
+            hierarchy: ContractionHierarchy {
+                ranks: HashMap::with_capacity(network.num_nodes()),
+                upward_edges: HashMap::with_capacity(network.num_nodes()),
+                downward_edges: HashMap::with_capacity(network.num_nodes()),
+                middle_nodes: HashMap::with_capacity(network.num_edges()),
+            },
+        }
+    }
+
+
+    fn run(mut self) -> ContractionHierarchy {
+        let mut priorities = HashMap::with_capacity(self.network.num_nodes());
+        let mut heap = BinaryHeap::with_capacity(self.network.num_nodes());
+        for (node_index, _) in self.network.nodes_iter() {
+            let priority = self.priority(*node_index);
+            priorities.insert(*node_index, priority);
+            heap.push(Reverse((priority, *node_index)));
+        }
+
+        while let Some(Reverse((priority, node_index))) = heap.pop() {
+            if priorities.get(&node_index) != Some(&priority) {
+                continue;
+            }
+
+            // Priorities go stale as the graph changes, so recompute lazily and only
+            // contract the node if it is still the least important.
+            let priority = self.priority(node_index);
+            if let Some(&Reverse((next_priority, _))) = heap.peek() {
+                if priority > next_priority {
+                    priorities.insert(node_index, priority);
+                    heap.push(Reverse((priority, node_index)));
+                    continue;
+                }
+            }
+            priorities.remove(&node_index);
+
+            let neighbours = self.contract(node_index);
+            for neighbour in neighbours {
+                let priority = self.priority(neighbour);
+                priorities.insert(neighbour, priority);
+                heap.push(Reverse((priority, neighbour)));
+            }
+        }
+
+        self.hierarchy
+    }
+
+
+    /// Edge difference plus the number of neighbours that have already been contracted.
+    fn priority(&self, node_index: NodeIndex) -> i64 {
+        let shortcuts = self.shortcuts(node_index).len() as i64;
+        let removed_edges = (self.out_edges[&node_index].len() + self.in_edges[&node_index].len()) as i64;
+        let deleted_neighbours = self.deleted_neighbours.get(&node_index).cloned().unwrap_or(0);
+        shortcuts - removed_edges + deleted_neighbours
+    }
+
+
+    /// The shortcuts needed to preserve shortest paths through a node if it were removed.
+    fn shortcuts(&self, node_index: NodeIndex) -> Vec<Shortcut> {
+        let mut shortcuts = Vec::new();
+        let out_edges = &self.out_edges[&node_index];
+
+        for (&from, &(in_cost, _)) in self.in_edges[&node_index].iter() {
+            let max_cost = out_edges.iter()
+                .filter(|&(to, _)| *to != from)
+                .map(|(_, &(out_cost, _))| in_cost + out_cost)
+                .max();
+            let max_cost = match max_cost {
+                Some(max_cost) => max_cost,
+                None => continue,
+            };
+
+            let witness_costs = self.witness_search(from, node_index, max_cost);
+            for (&to, &(out_cost, _)) in out_edges.iter().filter(|&(to, _)| *to != from) {
+                let cost = in_cost + out_cost;
+                if witness_costs.get(&to).is_none_or(|witness_cost| *witness_cost > cost) {
+                    shortcuts.push(Shortcut {from, to, cost});
+                }
+            }
+        }
+
+        shortcuts
+    }
+
+
+    /// Costs from a node to its neighbourhood without passing through the ignored node.
+    fn witness_search(&self, source: NodeIndex, ignored: NodeIndex, max_cost: Cost) -> HashMap<NodeIndex, Cost> {
+        let mut heap = BinaryHeap::new();
+        let mut costs = HashMap::new();
+        let mut settled = HashSet::new();
+
+        costs.insert(source, 0);
+        heap.push(Reverse((0, source)));
+
+        while let Some(Reverse((cost, node_index))) = heap.pop() {
+            if cost > max_cost || settled.len() >= MAX_WITNESS_SETTLED_NODES {
+                break;
+            }
+            if !settled.insert(node_index) {
+                continue;
+            }
+
+            for (&neighbour, &(edge_cost, _)) in self.out_edges[&node_index].iter() {
+                if neighbour == ignored {
+                    continue;
+                }
+                let neighbour_cost = cost + edge_cost;
+                if costs.get(&neighbour).is_none_or(|current| neighbour_cost < *current) {
+                    costs.insert(neighbour, neighbour_cost);
+                    heap.push(Reverse((neighbour_cost, neighbour)));
+                }
+            }
+        }
+
+        costs
+    }
+
+
+    /// Remove a node from the graph, moving its edges into the hierarchy and adding
+    /// shortcuts between its neighbours. Returns the neighbours of the node.
+    fn contract(&mut self, node_index: NodeIndex) -> Vec<NodeIndex> {
+        let shortcuts = self.shortcuts(node_index);
+        let rank = self.hierarchy.ranks.len();
+        self.hierarchy.ranks.insert(node_index, rank);
+
+        let out_edges = self.out_edges.remove(&node_index).unwrap();
+        let in_edges = self.in_edges.remove(&node_index).unwrap();
+
+        let mut neighbours = HashSet::new();
+        for (&to, &(cost, middle)) in out_edges.iter() {
+            self.hierarchy.upward_edges.entry(node_index).or_default().push(HierarchyEdge {node: to, cost});
+            self.hierarchy.middle_nodes.insert((node_index, to), middle);
+            self.in_edges.get_mut(&to).unwrap().remove(&node_index);
+            neighbours.insert(to);
+        }
+        for (&from, &(cost, middle)) in in_edges.iter() {
+            self.hierarchy.downward_edges.entry(node_index).or_default().push(HierarchyEdge {node: from, cost});
+            self.hierarchy.middle_nodes.insert((from, node_index), middle);
+            self.out_edges.get_mut(&from).unwrap().remove(&node_index);
+            neighbours.insert(from);
+        }
+
+        for shortcut in shortcuts {
+            insert_min_edge(self.out_edges.get_mut(&shortcut.from).unwrap(), shortcut.to, shortcut.cost, Some(node_index));
+            insert_min_edge(self.in_edges.get_mut(&shortcut.to).unwrap(), shortcut.from, shortcut.cost, Some(node_index));
+        }
+
+        for neighbour in neighbours.iter() {
+            *self.deleted_neighbours.entry(*neighbour).or_insert(0) += 1;
+        }
+        neighbours.into_iter().collect()
+    }
+}
+
+
+fn insert_min_edge(edges: &mut ContractionEdges, node_index: NodeIndex, cost: Cost, middle: Option<NodeIndex>) {
+    let edge = edges.entry(node_index).or_insert((cost, middle));
+    if cost < edge.0 {
+        *edge = (cost, middle);
+    }
+}
+
+
+/// One half of a bidirectional upward search.
+struct UpwardSearch<'a> {
+    edges: &'a HashMap<NodeIndex, Vec<HierarchyEdge>>,
+    /// Edges into each node from more important nodes in the direction of the search.
+    stall_edges: &'a HashMap<NodeIndex, Vec<HierarchyEdge>>,
+    heap: BinaryHeap<Reverse<(Cost, NodeIndex)>>,
+    costs: HashMap<NodeIndex, Cost>,
+    parents: HashMap<NodeIndex, NodeIndex>,
+    settled: HashSet<NodeIndex>,
+    statistics: SearchStatistics,
+}
+
+
+impl<'a> UpwardSearch<'a> {
+
+    fn new(edges: &'a HashMap<NodeIndex, Vec<HierarchyEdge>>,
+           stall_edges: &'a HashMap<NodeIndex, Vec<HierarchyEdge>>,
+           source: NodeIndex) -> Self {
+        let mut search = UpwardSearch {
+            edges,
+            stall_edges,
+            heap: BinaryHeap::new(),
+            costs: HashMap::new(),
+            parents: HashMap::new(),
+            settled: HashSet::new(),
+            statistics: SearchStatistics::default(),
+        };
+        search.costs.insert(source, 0);
+        search.heap.push(Reverse((0, source)));
+        search.statistics.record_push(search.heap.len());
+        search
+    }
+
+
+    fn min_cost(&self) -> Option<Cost> {
+        self.heap.peek().map(|&Reverse((cost, _))| cost)
+    }
+
+
+    /// Settle the next node, returning it with its tentative cost.
+    fn step(&mut self) -> Option<(NodeIndex, Cost)> {
+        while let Some(Reverse((cost, node_index))) = self.heap.pop() {
+            if !self.settled.insert(node_index) {
+                continue;
+            }
+            self.statistics.settled_nodes += 1;
+
+            if !self.is_stalled(node_index, cost) {
+                for edge in self.edges.get(&node_index).into_iter().flatten() {
+                    let neighbour_cost = cost + edge.cost;
+                    self.statistics.relaxed_edges += 1;
+                    if self.costs.get(&edge.node).is_none_or(|current| neighbour_cost < *current) {
+                        self.costs.insert(edge.node, neighbour_cost);
+                        self.parents.insert(edge.node, node_index);
+                        self.heap.push(Reverse((neighbour_cost, edge.node)));
+                        self.statistics.record_push(self.heap.len());
+                    }
+                }
+            }
+            return Some((node_index, cost));
+        }
+        None
+    }
+
+
+    /// A node is stalled when a more important node already reached offers a cheaper way
+    /// into it, so the upward search can't be on a shortest path through here.
+    fn is_stalled(&self, node_index: NodeIndex, cost: Cost) -> bool {
+        self.stall_edges.get(&node_index).into_iter().flatten().any(|edge| {
+            self.costs.get(&edge.node).is_some_and(|higher_cost| higher_cost + edge.cost < cost)
+        })
+    }
+
+
+    /// The hierarchy nodes from the source of the search to the given node.
+    fn path_to(&self, node_index: NodeIndex) -> Vec<NodeIndex> {
+        let mut path = vec![node_index];
+        let mut current = node_index;
+        while let Some(parent) = self.parents.get(&current) {
+            path.push(*parent);
+            current = *parent;
+        }
+        path.reverse();
+        path
+    }
+}
+
+
+/// Answer a shortest path query with a bidirectional upward search of the hierarchy.
+///
+/// The shortcuts on the resulting path are unpacked, so it is made up of nodes of the
+/// original network.
+pub fn ch_shortest_path(hierarchy: &ContractionHierarchy,
+                        start_node: &Node,
+                        end_node: &Node,
+) -> Option<ShortestPath> {
+    if !hierarchy.ranks.contains_key(&start_node.id) || !hierarchy.ranks.contains_key(&end_node.id) {
+        return None;
+    }
+    let start_time = Instant::now();
+
+    let mut forward = UpwardSearch::new(&hierarchy.upward_edges, &hierarchy.downward_edges, start_node.id);
+    let mut backward = UpwardSearch::new(&hierarchy.downward_edges, &hierarchy.upward_edges, end_node.id);
+    let mut best: Option<(Cost, NodeIndex)> = None;
+
+    loop {
+        let is_useful = |min_cost: Option<Cost>| match (min_cost, best) {
+            (Some(min_cost), Some((best_cost, _))) => min_cost < best_cost,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        let forward_useful = is_useful(forward.min_cost());
+        let backward_useful = is_useful(backward.min_cost());
+
+        let (search, other) = match (forward_useful, backward_useful) {
+            (false, false) => break,
+            (true, false) => (&mut forward, &backward),
+            (false, true) => (&mut backward, &forward),
+            (true, true) => {
+                if forward.min_cost() <= backward.min_cost() {
+                    (&mut forward, &backward)
+                } else {
+                    (&mut backward, &forward)
+                }
+            },
+        };
+
+        if let Some((node_index, cost)) = search.step() {
+            if let Some(other_cost) = other.costs.get(&node_index) {
+                let total = cost + other_cost;
+                if best.is_none_or(|(best_cost, _)| total < best_cost) {
+                    best = Some((total, node_index));
+                }
+            }
+        }
+    }
+
+    let (cost, meeting_node) = best?;
+
+    let upward_path = forward.path_to(meeting_node);
+    let mut downward_path = backward.path_to(meeting_node);
+    downward_path.reverse();
+
+    let mut path = vec![start_node.id];
+    for (from, to) in upward_path.iter().zip(upward_path.iter().skip(1)) {
+        hierarchy.unpack_edge(*from, *to, &mut path);
+    }
+    for (from, to) in downward_path.iter().zip(downward_path.iter().skip(1)) {
+        hierarchy.unpack_edge(*from, *to, &mut path);
+    }
+
+    let mut statistics = forward.statistics;
+    statistics.add(&backward.statistics);
+    statistics.duration = start_time.elapsed();
+
+    Some(ShortestPath {cost, path, statistics: Some(statistics)})
+}
+
+
+/// The costs between every source and every target using buckets.
+///
+/// An upward search from each target leaves its cost in a bucket at every node it
+/// reaches, then an upward search from each source combines its costs with the
+/// buckets of the nodes it reaches.
+pub fn ch_many_to_many(hierarchy: &ContractionHierarchy,
+                       sources: &[NodeIndex],
+                       targets: &[NodeIndex]) -> CostMatrix {
+    let mut matrix = CostMatrix::new(sources, targets);
+
+    let mut buckets: HashMap<NodeIndex, Vec<(usize, Cost)>> = HashMap::new();
+    for (target_index, target) in targets.iter().enumerate() {
+        if !hierarchy.ranks.contains_key(target) {
+            continue;
+        }
+        let mut search = UpwardSearch::new(&hierarchy.downward_edges, &hierarchy.upward_edges, *target);
+        while let Some((node_index, cost)) = search.step() {
+            buckets.entry(node_index).or_default().push((target_index, cost));
+        }
+    }
+
+    for (source_index, source) in sources.iter().enumerate() {
+        if !hierarchy.ranks.contains_key(source) {
+            continue;
+        }
+        let mut search = UpwardSearch::new(&hierarchy.upward_edges, &hierarchy.downward_edges, *source);
+        while let Some((node_index, cost)) = search.step() {
+            for (target_index, target_cost) in buckets.get(&node_index).into_iter().flatten() {
+                matrix.set_cost(source_index, *target_index, cost + target_cost);
+            }
+        }
+    }
+
+    matrix
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::contraction_hierarchies::*;
+    use crate::geo_utils::Location;
+    use crate::matrix::many_to_many;
+    use crate::shortest_path::dijkstra_shortest_path;
+    use crate::test_utils::build_grid_network;
+
+
+    fn path_cost(network: &RoadNetwork, path: &[NodeIndex]) -> Cost {
+        path.iter()
+            .zip(path.iter().skip(1))
+            .map(|(from, to)| {
+                network.get_node(*from).unwrap()
+                    .neighbours
+                    .iter()
+                    .filter(|edge| edge.destination == *to)
+                    .map(|edge| edge.cost)
+                    .min()
+                    .unwrap()
+            })
+            .sum()
+    }
+
+
+    fn check_matches_dijkstra(network: &RoadNetwork) {
+        let hierarchy = ContractionHierarchy::new(network);
+
+        for (start_index, start) in network.nodes_iter() {
+            for (end_index, end) in network.nodes_iter() {
+                let dijkstra = dijkstra_shortest_path(network, start, end);
+                let ch = ch_shortest_path(&hierarchy, start, end);
+
+                match (dijkstra, ch) {
+                    (None, None) => {},
+                    (Some(dijkstra), Some(ch)) => {
+                        assert_eq!(dijkstra.cost, ch.cost);
+                        assert_eq!(Some(start_index), ch.path.first());
+                        assert_eq!(Some(end_index), ch.path.last());
+                        assert_eq!(ch.cost, path_cost(network, &ch.path));
+                    },
+                    (dijkstra, ch) => panic!("Dijkstra found {:?} but CH found {:?}", dijkstra, ch),
+                }
+            }
+        }
+    }
+
+
+    #[test]
+    fn test_grid_network() {
+        check_matches_dijkstra(&build_grid_network(6, 6));
+    }
+
+
+    #[test]
+    fn test_network_with_one_way_streets() {
+        let mut network = RoadNetwork::new();
+        for i in 1..7 {
+            network.add_node(Node::new(i, Location::new(0., 0.))).unwrap();
+        }
+
+        network.add_edge(1, 2, 5);
+        network.add_edge(2, 3, 10);
+        network.add_edge(3, 4, 20);
+        network.add_edge(1, 4, 100);
+        network.add_edge(4, 5, 1);
+        network.add_edge(5, 1, 1);
+        network.add_edge(3, 1, 3);
+
+        check_matches_dijkstra(&network);
+    }
+
+
+    #[test]
+    fn test_every_node_is_ranked() {
+        let network = build_grid_network(4, 4);
+        let hierarchy = ContractionHierarchy::new(&network);
+
+        let mut ranks: Vec<_> = (0..16).map(|node_index| hierarchy.rank(node_index).unwrap()).collect();
+        ranks.sort();
+        assert_eq!((0..16).collect::<Vec<_>>(), ranks);
+    }
+
+
+    #[test]
+    fn test_many_to_many_matches_dijkstra_matrix() {
+        let network = build_grid_network(5, 5);
+        let hierarchy = ContractionHierarchy::new(&network);
+        let sources = vec![0, 7, 12, 24];
+        let targets = vec![3, 12, 20, 0, 3, 99];
+
+        assert_eq!(many_to_many(&network, &sources, &targets),
+                   ch_many_to_many(&hierarchy, &sources, &targets));
+    }
+}
@@ -0,0 +1,518 @@
+use crate::partition::{inertial_flow_bisection, sort_along_widest_dimension, InertialFlowParameters};
+use crate::road_network::{Cost, Edge, RoadNetwork, Node, NodeIndex};
+use crate::shortest_path::{SearchStatistics, ShortestPath};
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::error::Error;
+use std::time::Instant;
+
+
+/// A division of the network into cells on several levels, where every cell of a level
+/// is a union of cells of the level below.
+///
+/// The partition only depends on the shape of the network, not on its costs, so it can
+/// be reused by any metric.
+#[derive(Clone, Debug)]
+pub struct MultilevelPartition {
+    /// For each level, finest first, the cell of every node.
+    cells: Vec<HashMap<NodeIndex, usize>>,
+    num_cells: Vec<usize>,
+}
+
+
+impl MultilevelPartition {
+
+    /// Recursively split the network in half at the median of the widest dimension of
+    /// its node locations, until the cells of each level have at most that level's
+    /// number of nodes. `max_cell_sizes` must be increasing, finest level first.
+    pub fn kd_tree(network: &RoadNetwork, max_cell_sizes: &[usize]) -> Result<Self, Box<dyn Error>> {
+        MultilevelPartition::from_bisection(network, max_cell_sizes, |nodes| {
+            let mut nodes: Vec<_> = nodes.iter()
+                .map(|node_index| {
+                    let location = network.get_node(*node_index).unwrap().location;
+                    (*node_index, location.lat(), location.lng())
+                })
+                .collect();
+            sort_along_widest_dimension(&mut nodes);
+            let (lower, upper) = nodes.split_at(nodes.len() / 2);
+            (lower.iter().map(|node| node.0).collect(), upper.iter().map(|node| node.0).collect())
+        })
+    }
+
+
+    /// Recursively split the network with inertial flow, which finds cells with fewer
+    /// roads between them than `kd_tree` at the cost of a slower partition.
+    pub fn inertial_flow(network: &RoadNetwork,
+                         max_cell_sizes: &[usize],
+                         parameters: &InertialFlowParameters,
+    ) -> Result<Self, Box<dyn Error>> {
+        MultilevelPartition::from_bisection(network, max_cell_sizes, |nodes| {
+            let bisection = inertial_flow_bisection(network, nodes, parameters);
+            (bisection.first, bisection.second)
+        })
+    }
+
+
+    /// Build the levels by splitting the network in two with `bisect` until cells are
+    /// small enough.
+    fn from_bisection<B>(network: &RoadNetwork, max_cell_sizes: &[usize], mut bisect: B) -> Result<Self, Box<dyn Error>>
+        where B: FnMut(&[NodeIndex]) -> (Vec<NodeIndex>, Vec<NodeIndex>)
+    {
+        if max_cell_sizes.is_empty() {
+            return Err(From::from("A partition needs at least one level"));
+        }
+        if max_cell_sizes[0] == 0 || max_cell_sizes.windows(2).any(|sizes| sizes[0] >= sizes[1]) {
+            return Err(From::from(format!("Cell sizes must be positive and increasing, not {:?}", max_cell_sizes)));
+        }
+
+        let mut partition = MultilevelPartition {
+            cells: vec![HashMap::new(); max_cell_sizes.len()],
+            num_cells: vec![0; max_cell_sizes.len()],
+        };
+        let mut nodes: Vec<_> = network.nodes_iter().map(|(node_index, _)| *node_index).collect();
+        nodes.sort();
+
+        // Ranges of nodes still to split, with the number of levels they have no cell on yet.
+        let mut stack = vec![(nodes, max_cell_sizes.len())];
+        while let Some((nodes, mut unassigned_levels)) = stack.pop() {
+            while unassigned_levels > 0 && nodes.len() <= max_cell_sizes[unassigned_levels - 1] {
+                let level = unassigned_levels - 1;
+                let cell = partition.num_cells[level];
+                partition.num_cells[level] += 1;
+                partition.cells[level].extend(nodes.iter().map(|node_index| (*node_index, cell)));
+                unassigned_levels -= 1;
+            }
+            if unassigned_levels == 0 {
+                continue;
+            }
+            let (first, second) = bisect(&nodes);
+            if first.is_empty() || second.is_empty() {
+                return Err(From::from(format!("Could not split a cell of {} nodes", nodes.len())));
+            }
+            stack.push((second, unassigned_levels));
+            stack.push((first, unassigned_levels));
+        }
+
+        Ok(partition)
+    }
+
+
+    pub fn num_levels(&self) -> usize {
+        self.cells.len()
+    }
+
+
+    pub fn num_cells(&self, level: usize) -> usize {
+        self.num_cells[level]
+    }
+
+
+    /// The cell of a node on a level, with level 0 the finest.
+    pub fn cell(&self, level: usize, node_index: NodeIndex) -> Option<usize> {
+        self.cells.get(level).and_then(|cells| cells.get(&node_index)).cloned()
+    }
+}
+
+
+/// The metric independent part of customizable route planning: the boundary nodes of
+/// every cell, which are joined by a clique of shortcuts once a metric is customized.
+pub struct Overlay<'a> {
+    network: &'a RoadNetwork,
+    partition: MultilevelPartition,
+    /// For each level and cell, the nodes with an edge into or out of the cell.
+    boundary_nodes: Vec<Vec<Vec<NodeIndex>>>,
+    /// For each level, the position of every boundary node in its cell's list.
+    boundary_positions: Vec<HashMap<NodeIndex, usize>>,
+}
+
+
+/// The costs of one metric: the cost of every edge, and of the shortcuts between the
+/// boundary nodes of every cell.
+pub struct Customization {
+    /// The costs of each node's edges, in the same order as its `neighbours`, or None
+    /// for closed roads.
+    edge_costs: HashMap<NodeIndex, Vec<Option<Cost>>>,
+    /// For each level and cell, the costs between its boundary nodes as a square matrix
+    /// stored row by row, or None where there's no path within the cell.
+    cliques: Vec<Vec<Vec<Option<Cost>>>>,
+}
+
+
+impl<'a> Overlay<'a> {
+
+    pub fn new(network: &'a RoadNetwork, partition: MultilevelPartition) -> Self {
+        let mut boundary_nodes = Vec::with_capacity(partition.num_levels());
+        let mut boundary_positions = Vec::with_capacity(partition.num_levels());
+
+        for level in 0..partition.num_levels() {
+            let mut nodes: Vec<_> = network.nodes_iter().map(|(node_index, _)| *node_index).collect();
+            nodes.sort();
+            let mut cells = vec![Vec::new(); partition.num_cells(level)];
+            let mut positions = HashMap::new();
+            for node_index in nodes {
+                let node = network.get_node(node_index).unwrap();
+                let cell = partition.cell(level, node_index).unwrap();
+                let crosses = |other: NodeIndex| partition.cell(level, other) != Some(cell);
+                let is_boundary = node.neighbours.iter().any(|edge| crosses(edge.destination))
+                    || node.reverse_neighbours.iter().any(|edge| crosses(edge.origin));
+                if is_boundary {
+                    positions.insert(node_index, cells[cell].len());
+                    cells[cell].push(node_index);
+                }
+            }
+            boundary_nodes.push(cells);
+            boundary_positions.push(positions);
+        }
+
+        Overlay {network, partition, boundary_nodes, boundary_positions}
+    }
+
+
+    pub fn network(&self) -> &'a RoadNetwork {
+        self.network
+    }
+
+
+    pub fn partition(&self) -> &MultilevelPartition {
+        &self.partition
+    }
+
+
+    /// The number of boundary nodes of all cells of a level.
+    pub fn num_boundary_nodes(&self, level: usize) -> usize {
+        self.boundary_positions[level].len()
+    }
+
+
+    /// Compute the costs of a metric, which gives the cost of each edge leaving a node,
+    /// or None to close it.
+    ///
+    /// Each level's shortcuts are found by searches within each cell, over the original
+    /// edges on the finest level and over the shortcuts of the level below above that,
+    /// so only this has to be redone when costs change.
+    pub fn customize<M>(&self, metric: M) -> Customization
+        where M: Fn(NodeIndex, &Edge) -> Option<Cost>
+    {
+        let edge_costs = self.network.nodes_iter()
+            .map(|(node_index, node)| {
+                (*node_index, node.neighbours.iter().map(|edge| metric(*node_index, edge)).collect())
+            })
+            .collect();
+        let mut customization = Customization {edge_costs, cliques: Vec::with_capacity(self.partition.num_levels())};
+
+        for level in 0..self.partition.num_levels() {
+            let cliques = self.boundary_nodes[level].iter()
+                .enumerate()
+                .map(|(cell, boundary)| {
+                    let mut clique = vec![None; boundary.len() * boundary.len()];
+                    for (row, from) in boundary.iter().enumerate() {
+                        let reached = self.search(&customization, *from, None, |_| level, |node_index| {
+                            self.partition.cell(level, node_index) == Some(cell)
+                        }, &mut SearchStatistics::default());
+                        for (column, to) in boundary.iter().enumerate() {
+                            clique[row * boundary.len() + column] = reached.get(to).map(|(cost, _)| *cost);
+                        }
+                    }
+                    clique
+                })
+                .collect();
+            customization.cliques.push(cliques);
+        }
+
+        customization
+    }
+
+
+    /// The edges out of a node when searching on an overlay level, where level 0 is the
+    /// original network and level `l` uses the shortcuts of the cells of partition level
+    /// `l - 1` along with the original edges leaving them.
+    fn edges(&self, customization: &Customization, level: usize, node_index: NodeIndex) -> Vec<(NodeIndex, Cost)> {
+        let node = self.network.get_node(node_index).unwrap();
+        let costs = &customization.edge_costs[&node_index];
+        let original_edges = node.neighbours.iter()
+            .zip(costs.iter())
+            .filter_map(|(edge, cost)| cost.map(|cost| (edge.destination, cost)));
+        if level == 0 {
+            return original_edges.collect();
+        }
+
+        let partition_level = level - 1;
+        let cell = self.partition.cell(partition_level, node_index);
+        let mut edges: Vec<_> = original_edges
+            .filter(|(destination, _)| self.partition.cell(partition_level, *destination) != cell)
+            .collect();
+        if let (Some(cell), Some(row)) = (cell, self.boundary_positions[partition_level].get(&node_index)) {
+            let boundary = &self.boundary_nodes[partition_level][cell];
+            let clique = &customization.cliques[partition_level][cell];
+            for (column, to) in boundary.iter().enumerate() {
+                if let Some(cost) = clique[row * boundary.len() + column] {
+                    if column != *row {
+                        edges.push((*to, cost));
+                    }
+                }
+            }
+        }
+        edges
+    }
+
+
+    /// Dijkstra's algorithm from a node, searching each node on the level given for it
+    /// and only entering allowed nodes, until the end node is settled if there is one.
+    /// Returns the cost and parent of every settled node.
+    fn search<L, A>(&self,
+                    customization: &Customization,
+                    start: NodeIndex,
+                    end: Option<NodeIndex>,
+                    level_of: L,
+                    allowed: A,
+                    statistics: &mut SearchStatistics,
+    ) -> HashMap<NodeIndex, (Cost, NodeIndex)>
+        where L: Fn(NodeIndex) -> usize,
+              A: Fn(NodeIndex) -> bool
+    {
+        let mut settled = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        heap.push(Reverse((0, start, start)));
+        statistics.record_push(heap.len());
+
+        while let Some(Reverse((cost, node_index, parent))) = heap.pop() {
+            if settled.contains_key(&node_index) {
+                continue;
+            }
+            settled.insert(node_index, (cost, parent));
+            statistics.settled_nodes += 1;
+            if end == Some(node_index) {
+                break;
+            }
+
+            for (destination, edge_cost) in self.edges(customization, level_of(node_index), node_index) {
+                statistics.relaxed_edges += 1;
+                if allowed(destination) && !settled.contains_key(&destination) {
+                    heap.push(Reverse((cost + edge_cost, destination, node_index)));
+                    statistics.record_push(heap.len());
+                }
+            }
+        }
+
+        settled
+    }
+
+
+    /// The overlay level to search a node on: the number of partition levels where it
+    /// is in a different cell from both ends of the route.
+    fn query_level(&self, start: NodeIndex, end: NodeIndex, node_index: NodeIndex) -> usize {
+        (0..self.partition.num_levels())
+            .take_while(|level| {
+                let cell = self.partition.cell(*level, node_index);
+                cell != self.partition.cell(*level, start) && cell != self.partition.cell(*level, end)
+            })
+            .count()
+    }
+
+
+    /// Replace a shortcut within a cell of a partition level by the original edges.
+    fn unpack_shortcut(&self,
+                       customization: &Customization,
+                       partition_level: usize,
+                       from: NodeIndex,
+                       to: NodeIndex,
+                       path: &mut Vec<NodeIndex>) {
+        let cell = self.partition.cell(partition_level, from);
+        let reached = self.search(customization, from, Some(to), |_| 0, |node_index| {
+            self.partition.cell(partition_level, node_index) == cell
+        }, &mut SearchStatistics::default());
+        let start = path.len();
+        let mut node_index = to;
+        while node_index != from {
+            path.push(node_index);
+            node_index = reached[&node_index].1;
+        }
+        path[start..].reverse();
+    }
+}
+
+
+/// A search on the overlay, which uses the original edges near both ends of the route
+/// and the shortcuts of the coarsest cell that contains neither everywhere else.
+///
+/// Shortcuts on the path are unpacked into the original edges by searching within their
+/// cells, so the path is the same as one found by Dijkstra's algorithm on the metric.
+pub fn crp_shortest_path(overlay: &Overlay,
+                         customization: &Customization,
+                         start_node: &Node,
+                         end_node: &Node,
+) -> Option<ShortestPath> {
+    let start_time = Instant::now();
+    let mut statistics = SearchStatistics::default();
+    let (start, end) = (start_node.id, end_node.id);
+    overlay.partition.cell(0, start)?;
+    overlay.partition.cell(0, end)?;
+
+    let reached = overlay.search(customization, start, Some(end), |node_index| {
+        overlay.query_level(start, end, node_index)
+    }, |_| true, &mut statistics);
+    let cost = reached.get(&end)?.0;
+
+    let mut overlay_path = vec![end];
+    while overlay_path[overlay_path.len() - 1] != start {
+        overlay_path.push(reached[&overlay_path[overlay_path.len() - 1]].1);
+    }
+    overlay_path.reverse();
+
+    let mut path = vec![start];
+    for pair in overlay_path.windows(2) {
+        let level = overlay.query_level(start, end, pair[0]);
+        if level > 0 && overlay.partition.cell(level - 1, pair[0]) == overlay.partition.cell(level - 1, pair[1]) {
+            overlay.unpack_shortcut(customization, level - 1, pair[0], pair[1], &mut path);
+        } else {
+            path.push(pair[1]);
+        }
+    }
+
+    statistics.duration = start_time.elapsed();
+    Some(ShortestPath {cost, path, statistics: Some(statistics)})
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::customizable_route_planning::*;
+    use crate::partition::PartitionReport;
+    use crate::shortest_path::dijkstra_shortest_path;
+    use crate::test_utils::build_grid_network;
+
+
+    /// A copy of the network with the costs of a metric, leaving out closed roads.
+    fn apply_metric<M: Fn(NodeIndex, &Edge) -> Option<Cost>>(network: &RoadNetwork, metric: M) -> RoadNetwork {
+        let mut customized = RoadNetwork::new();
+        for (node_index, node) in network.nodes_iter() {
+            customized.add_node(Node::new(*node_index, node.location)).unwrap();
+        }
+        for (node_index, node) in network.nodes_iter() {
+            for edge in node.neighbours.iter() {
+                if let Some(cost) = metric(*node_index, edge) {
+                    customized.add_edge(*node_index, edge.destination, cost);
+                }
+            }
+        }
+        customized
+    }
+
+
+    fn check_matches_dijkstra(network: &RoadNetwork, overlay: &Overlay, customization: &Customization) {
+        for (_, start) in network.nodes_iter() {
+            for (_, end) in network.nodes_iter() {
+                let expected = dijkstra_shortest_path(network, start, end);
+                let result = crp_shortest_path(overlay, customization, start, end);
+                assert_eq!(expected.as_ref().map(|path| path.cost()), result.as_ref().map(|path| path.cost()));
+
+                // The unpacked path must follow edges of the network and add up to its cost.
+                if let Some(result) = result {
+                    let path_cost: Cost = result.path().windows(2)
+                        .map(|pair| network.edge_cost(pair[0], pair[1]).unwrap())
+                        .sum();
+                    assert_eq!(result.cost(), path_cost);
+                    assert_eq!(Some(&start.id), result.path().first());
+                    assert_eq!(Some(&end.id), result.path().last());
+                }
+            }
+        }
+    }
+
+
+    #[test]
+    fn test_kd_tree_partition() {
+        let network = build_grid_network(8, 8);
+        let partition = MultilevelPartition::kd_tree(&network, &[4, 16, 32]).unwrap();
+
+        assert_eq!(3, partition.num_levels());
+        assert_eq!(vec![16, 4, 2], (0..3).map(|level| partition.num_cells(level)).collect::<Vec<_>>());
+        for level in 0..3 {
+            for cell in 0..partition.num_cells(level) {
+                let size = (0..64).filter(|node_index| partition.cell(level, *node_index) == Some(cell)).count();
+                assert_eq!([4, 16, 32][level], size);
+            }
+        }
+
+        // Nodes sharing a cell share a cell on every coarser level too.
+        for a in 0..64 {
+            for b in 0..64 {
+                if partition.cell(0, a) == partition.cell(0, b) {
+                    assert_eq!(partition.cell(1, a), partition.cell(1, b));
+                    assert_eq!(partition.cell(2, a), partition.cell(2, b));
+                }
+            }
+        }
+        assert_eq!(None, partition.cell(0, 64));
+        assert_eq!(None, partition.cell(3, 0));
+    }
+
+
+    #[test]
+    fn test_invalid_cell_sizes() {
+        let network = build_grid_network(2, 2);
+        assert!(MultilevelPartition::kd_tree(&network, &[]).is_err());
+        assert!(MultilevelPartition::kd_tree(&network, &[0]).is_err());
+        assert!(MultilevelPartition::kd_tree(&network, &[4, 4]).is_err());
+
+        let partition = MultilevelPartition::kd_tree(&network, &[10]).unwrap();
+        assert_eq!(1, partition.num_cells(0));
+    }
+
+
+    #[test]
+    fn test_matches_dijkstra() {
+        let network = build_grid_network(7, 7);
+        let overlay = Overlay::new(&network, MultilevelPartition::kd_tree(&network, &[4, 12, 25]).unwrap());
+        assert!(overlay.num_boundary_nodes(0) >= overlay.num_boundary_nodes(1));
+        assert!(overlay.num_boundary_nodes(1) >= overlay.num_boundary_nodes(2));
+
+        let customization = overlay.customize(|_, edge| Some(edge.cost));
+        check_matches_dijkstra(&network, &overlay, &customization);
+
+        // Away from the ends of a long route the search only settles boundary nodes.
+        let (start, end) = (network.get_node(0).unwrap(), network.get_node(48).unwrap());
+        let dijkstra = dijkstra_shortest_path(&network, start, end).unwrap();
+        let crp = crp_shortest_path(&overlay, &customization, start, end).unwrap();
+        assert!(crp.statistics().unwrap().settled_nodes < dijkstra.statistics().unwrap().settled_nodes);
+    }
+
+
+    #[test]
+    fn test_inertial_flow_partition() {
+        let network = build_grid_network(6, 6);
+        let partition = MultilevelPartition::inertial_flow(&network, &[6, 18], &Default::default()).unwrap();
+        for level in 0..2 {
+            let report = PartitionReport::new(&network, |node_index| partition.cell(level, node_index));
+            assert_eq!(0, report.unassigned_nodes);
+            assert!(report.max_cell_size <= [6, 18][level]);
+        }
+
+        let overlay = Overlay::new(&network, partition);
+        let customization = overlay.customize(|_, edge| Some(edge.cost));
+        check_matches_dijkstra(&network, &overlay, &customization);
+    }
+
+
+    #[test]
+    fn test_swapping_metrics() {
+        let network = build_grid_network(6, 6);
+        let overlay = Overlay::new(&network, MultilevelPartition::kd_tree(&network, &[4, 12]).unwrap());
+
+        // Slower on the residential roads inside the grid, as for a truck.
+        let truck = |_: NodeIndex, edge: &Edge| Some(if edge.cost > 10 { edge.cost * 3 } else { edge.cost });
+        let customization = overlay.customize(truck);
+        check_matches_dijkstra(&apply_metric(&network, truck), &overlay, &customization);
+
+        // Close every road into and out of two nodes, leaving one unreachable.
+        let closed = |from: NodeIndex, edge: &Edge| {
+            if [14, 30].contains(&from) || [14, 30].contains(&edge.destination) { None } else { Some(edge.cost) }
+        };
+        let customization = overlay.customize(closed);
+        let closed_network = apply_metric(&network, closed);
+        check_matches_dijkstra(&closed_network, &overlay, &customization);
+        assert!(crp_shortest_path(&overlay, &customization,
+                                  network.get_node(0).unwrap(), network.get_node(14).unwrap()).is_none());
+    }
+}
@@ -4,7 +4,7 @@ use self::geo::Point;
 use self::geo::algorithm::haversine_distance::HaversineDistance;
 
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Location {
     point: Point<f64>,
 }
@@ -15,6 +15,14 @@ impl Location {
         Location {point: Point::new(lng, lat)}
     }
 
+    pub fn lat(&self) -> f64 {
+        self.point.y()
+    }
+
+    pub fn lng(&self) -> f64 {
+        self.point.x()
+    }
+
     pub fn as_point(&self) -> &Point<f64> {
         &self.point
     }
@@ -24,3 +32,14 @@ impl Location {
 pub fn earth_distance(l1: &Location, l2: &Location) -> f64 {
     l1.as_point().haversine_distance(l2.as_point())
 }
+
+
+/// The initial compass bearing in degrees of the great circle from one location to another,
+/// clockwise from north.
+pub fn bearing(from: &Location, to: &Location) -> f64 {
+    let (lat1, lat2) = (from.lat().to_radians(), to.lat().to_radians());
+    let delta_lng = (to.lng() - from.lng()).to_radians();
+    let y = delta_lng.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * delta_lng.cos();
+    (y.atan2(x).to_degrees() + 360.) % 360.
+}
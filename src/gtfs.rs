@@ -0,0 +1,460 @@
+extern crate zip;
+
+use crate::geo_utils::Location;
+use crate::road_network::Cost;
+use crate::time_utils::{date_from_days, days_since_epoch};
+
+use self::zip::ZipArchive;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+
+/// A date written as the number `YYYYMMDD`, the way GTFS writes dates.
+pub type ServiceDate = u32;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stop {
+    pub id: String,
+    pub name: String,
+    pub location: Location,
+}
+
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Trip {
+    pub id: String,
+    pub route_id: String,
+    pub service_id: String,
+}
+
+
+/// A visit of a trip to a stop. Times are seconds since midnight of the day the trip
+/// runs, and may be more than a day for trips running past midnight. Stops which aren't
+/// timepoints may have no times.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StopTime {
+    pub trip_id: String,
+    pub arrival_time: Option<Cost>,
+    pub departure_time: Option<Cost>,
+    pub stop_id: String,
+    pub stop_sequence: u32,
+}
+
+
+/// The days a service runs each week between two dates, from `calendar.txt`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Service {
+    pub id: String,
+    /// Whether the service runs on each day of the week, starting with Monday.
+    pub days: [bool; 7],
+    pub start_date: ServiceDate,
+    pub end_date: ServiceDate,
+}
+
+
+/// A date a service runs or doesn't run despite its calendar, from `calendar_dates.txt`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ServiceException {
+    pub service_id: String,
+    pub date: ServiceDate,
+    pub added: bool,
+}
+
+
+/// The parts of a GTFS feed needed for routing.
+#[derive(Clone, Debug, Default)]
+pub struct GtfsFeed {
+    pub stops: Vec<Stop>,
+    pub trips: Vec<Trip>,
+    pub stop_times: Vec<StopTime>,
+    pub services: Vec<Service>,
+    pub service_exceptions: Vec<ServiceException>,
+}
+
+
+impl GtfsFeed {
+
+    /// Whether the service runs on a date, taking exceptions into account.
+    pub fn service_runs_on(&self, service_id: &str, date: ServiceDate) -> bool {
+        let exception = self.service_exceptions
+            .iter()
+            .find(|exception| exception.service_id == service_id && exception.date == date);
+        if let Some(exception) = exception {
+            return exception.added;
+        }
+
+        self.services.iter()
+            .filter(|service| service.id == service_id)
+            .any(|service| {
+                service.start_date <= date && date <= service.end_date && service.days[weekday(date)]
+            })
+    }
+}
+
+
+/// Read a GTFS feed from a zip file or a directory of its files.
+///
+/// `stops.txt`, `trips.txt` and `stop_times.txt` are required. `calendar.txt` and
+/// `calendar_dates.txt` are optional, but without either no trip ever runs.
+pub fn read_gtfs(path: &str) -> Result<GtfsFeed, Box<dyn Error>> {
+    let mut source = if Path::new(path).is_dir() {
+        FeedSource::Directory(PathBuf::from(path))
+    } else {
+        FeedSource::Zip(ZipArchive::new(File::open(path)?)?)
+    };
+
+    let required = |contents: Option<String>, name| {
+        contents.ok_or_else(|| format!("GTFS feed {} has no {}", path, name))
+    };
+
+    let mut feed = GtfsFeed {
+        stops: parse_stops(&required(source.read("stops.txt")?, "stops.txt")?)?,
+        trips: parse_trips(&required(source.read("trips.txt")?, "trips.txt")?)?,
+        stop_times: parse_stop_times(&required(source.read("stop_times.txt")?, "stop_times.txt")?)?,
+        ..GtfsFeed::default()
+    };
+    if let Some(contents) = source.read("calendar.txt")? {
+        feed.services = parse_calendar(&contents)?;
+    }
+    if let Some(contents) = source.read("calendar_dates.txt")? {
+        feed.service_exceptions = parse_calendar_dates(&contents)?;
+    }
+    Ok(feed)
+}
+
+
+enum FeedSource {
+    Directory(PathBuf),
+    Zip(ZipArchive<File>),
+}
+
+
+impl FeedSource {
+
+    /// The contents of one of the feed's files, or None if the feed doesn't have it.
+    fn read(&mut self, name: &str) -> Result<Option<String>, Box<dyn Error>> {
+        match self {
+            FeedSource::Directory(directory) => {
+                let path = directory.join(name);
+                if !path.exists() {
+                    return Ok(None);
+                }
+                Ok(Some(fs::read_to_string(path)?))
+            },
+            FeedSource::Zip(archive) => {
+                let mut file = match archive.by_name(name) {
+                    Ok(file) => file,
+                    Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+                    Err(e) => return Err(From::from(e)),
+                };
+                let mut contents = String::new();
+                file.read_to_string(&mut contents)?;
+                Ok(Some(contents))
+            },
+        }
+    }
+}
+
+
+/// The rows of a CSV file, with its columns looked up by the names in its header.
+struct Table<'a> {
+    name: &'a str,
+    columns: HashMap<String, usize>,
+    rows: Vec<(usize, Vec<String>)>,
+}
+
+
+impl<'a> Table<'a> {
+
+    fn parse(name: &'a str, contents: &str) -> Self {
+        let contents = contents.trim_start_matches('\u{feff}');
+        let mut lines = contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+
+        let columns = lines.next()
+            .map(|(_, header)| parse_csv_line(header)
+                .into_iter()
+                .enumerate()
+                .map(|(index, column)| (column.trim().to_string(), index))
+                .collect())
+            .unwrap_or_default();
+        let rows = lines.map(|(line_index, line)| (line_index + 1, parse_csv_line(line))).collect();
+
+        Table {name, columns, rows}
+    }
+
+
+    fn column(&self, column: &str) -> Result<usize, Box<dyn Error>> {
+        self.columns.get(column)
+            .cloned()
+            .ok_or_else(|| From::from(format!("{} has no {} column", self.name, column)))
+    }
+
+
+    /// Parse each row, adding the file name and line number to any error.
+    fn map_rows<T, F>(&self, parse_row: F) -> Result<Vec<T>, Box<dyn Error>>
+        where F: Fn(&Row) -> Result<T, Box<dyn Error>>
+    {
+        self.rows.iter()
+            .map(|(line_number, fields)| {
+                parse_row(&Row {fields})
+                    .map_err(|e| From::from(format!("{} line {}: {}", self.name, line_number, e)))
+            })
+            .collect()
+    }
+}
+
+
+struct Row<'a> {
+    fields: &'a [String],
+}
+
+
+impl<'a> Row<'a> {
+
+    /// The field in a column, or an empty string if the row is too short.
+    fn get(&self, column: usize) -> &'a str {
+        self.fields.get(column).map(|field| field.trim()).unwrap_or("")
+    }
+}
+
+
+/// Split a line of CSV into fields, allowing for quoted fields containing commas or quotes.
+fn parse_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            },
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+
+/// Parse a GTFS time `HH:MM:SS` into seconds since midnight.
+fn parse_time(time: &str) -> Result<Cost, Box<dyn Error>> {
+    let parts: Vec<_> = time.split(':').collect();
+    if parts.len() != 3 {
+        return Err(From::from(format!("Invalid time '{}'", time)));
+    }
+    let hours: Cost = parts[0].parse()?;
+    let minutes: Cost = parts[1].parse()?;
+    let seconds: Cost = parts[2].parse()?;
+    Ok(hours * 3600 + minutes * 60 + seconds)
+}
+
+
+fn parse_optional_time(time: &str) -> Result<Option<Cost>, Box<dyn Error>> {
+    if time.is_empty() { Ok(None) } else { parse_time(time).map(Some) }
+}
+
+
+/// The day of the week of a date, counting from zero on Monday.
+pub fn weekday(date: ServiceDate) -> usize {
+    let days = days_since_epoch((date / 10000) as i64, ((date / 100) % 100) as i64, (date % 100) as i64);
+    // 1970-01-01 was a Thursday.
+    (days + 3).rem_euclid(7) as usize
+}
+
+
+/// The date before a date.
+pub fn previous_date(date: ServiceDate) -> ServiceDate {
+    let days = days_since_epoch((date / 10000) as i64, ((date / 100) % 100) as i64, (date % 100) as i64);
+    let (year, month, day) = date_from_days(days - 1);
+    (year * 10000 + month * 100 + day) as ServiceDate
+}
+
+
+fn parse_stops(contents: &str) -> Result<Vec<Stop>, Box<dyn Error>> {
+    let table = Table::parse("stops.txt", contents);
+    let (id, name, lat, lon) = (table.column("stop_id")?, table.column("stop_name")?,
+                                table.column("stop_lat")?, table.column("stop_lon")?);
+    table.map_rows(|row| Ok(Stop {
+        id: row.get(id).to_string(),
+        name: row.get(name).to_string(),
+        location: Location::new(row.get(lat).parse()?, row.get(lon).parse()?),
+    }))
+}
+
+
+fn parse_trips(contents: &str) -> Result<Vec<Trip>, Box<dyn Error>> {
+    let table = Table::parse("trips.txt", contents);
+    let (id, route_id, service_id) = (table.column("trip_id")?, table.column("route_id")?,
+                                      table.column("service_id")?);
+    table.map_rows(|row| Ok(Trip {
+        id: row.get(id).to_string(),
+        route_id: row.get(route_id).to_string(),
+        service_id: row.get(service_id).to_string(),
+    }))
+}
+
+
+fn parse_stop_times(contents: &str) -> Result<Vec<StopTime>, Box<dyn Error>> {
+    let table = Table::parse("stop_times.txt", contents);
+    let (trip_id, arrival, departure, stop_id, sequence) = (
+        table.column("trip_id")?, table.column("arrival_time")?, table.column("departure_time")?,
+        table.column("stop_id")?, table.column("stop_sequence")?);
+    table.map_rows(|row| Ok(StopTime {
+        trip_id: row.get(trip_id).to_string(),
+        arrival_time: parse_optional_time(row.get(arrival))?,
+        departure_time: parse_optional_time(row.get(departure))?,
+        stop_id: row.get(stop_id).to_string(),
+        stop_sequence: row.get(sequence).parse()?,
+    }))
+}
+
+
+fn parse_calendar(contents: &str) -> Result<Vec<Service>, Box<dyn Error>> {
+    let table = Table::parse("calendar.txt", contents);
+    let day_columns = ["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"]
+        .iter()
+        .map(|day| table.column(day))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (id, start_date, end_date) = (table.column("service_id")?, table.column("start_date")?,
+                                      table.column("end_date")?);
+    table.map_rows(|row| {
+        let mut days = [false; 7];
+        for (day, column) in day_columns.iter().enumerate() {
+            days[day] = row.get(*column) == "1";
+        }
+        Ok(Service {
+            id: row.get(id).to_string(),
+            days,
+            start_date: row.get(start_date).parse()?,
+            end_date: row.get(end_date).parse()?,
+        })
+    })
+}
+
+
+fn parse_calendar_dates(contents: &str) -> Result<Vec<ServiceException>, Box<dyn Error>> {
+    let table = Table::parse("calendar_dates.txt", contents);
+    let (id, date, exception_type) = (table.column("service_id")?, table.column("date")?,
+                                      table.column("exception_type")?);
+    table.map_rows(|row| Ok(ServiceException {
+        service_id: row.get(id).to_string(),
+        date: row.get(date).parse()?,
+        added: match row.get(exception_type) {
+            "1" => true,
+            "2" => false,
+            other => return Err(From::from(format!("Invalid exception type '{}'", other))),
+        },
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::gtfs::*;
+
+    use std::io::Write;
+    use self::zip::write::{FileOptions, ZipWriter};
+
+
+    fn fixture_path() -> PathBuf {
+        Path::new("tests").join("fixtures").join("gtfs")
+    }
+
+
+    #[test]
+    fn test_read_gtfs_directory() {
+        let feed = read_gtfs(fixture_path().to_str().unwrap()).unwrap();
+
+        assert_eq!(5, feed.stops.len());
+        assert_eq!("Hauptbahnhof, Gleis 1", feed.stops[0].name);
+        assert_eq!(5, feed.trips.len());
+        assert_eq!(Some(&StopTime {
+            trip_id: "late".to_string(),
+            arrival_time: Some(25 * 3600),
+            departure_time: Some(25 * 3600),
+            stop_id: "C".to_string(),
+            stop_sequence: 2,
+        }), feed.stop_times.last());
+        assert_eq!(1, feed.services.len());
+        assert_eq!(1, feed.service_exceptions.len());
+
+        // The slow trip's middle stop isn't a timepoint.
+        let untimed: Vec<_> = feed.stop_times.iter()
+            .filter(|stop_time| stop_time.arrival_time.is_none() && stop_time.departure_time.is_none())
+            .map(|stop_time| (stop_time.trip_id.as_str(), stop_time.stop_id.as_str()))
+            .collect();
+        assert_eq!(vec![("slow", "B")], untimed);
+    }
+
+
+    #[test]
+    fn test_read_gtfs_zip() {
+        let zip_path = std::env::temp_dir().join(format!("gtfs_test_{}.zip", std::process::id()));
+        {
+            let mut writer = ZipWriter::new(File::create(&zip_path).unwrap());
+            for name in ["stops.txt", "trips.txt", "stop_times.txt", "calendar.txt"].iter() {
+                writer.start_file(*name, FileOptions::default()).unwrap();
+                writer.write_all(&fs::read(fixture_path().join(name)).unwrap()).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+
+        let feed = read_gtfs(zip_path.to_str().unwrap());
+        fs::remove_file(&zip_path).unwrap();
+        let feed = feed.unwrap();
+        assert_eq!(5, feed.stops.len());
+        assert!(feed.service_exceptions.is_empty());
+    }
+
+
+    #[test]
+    fn test_missing_feed() {
+        assert!(read_gtfs("not a file path").is_err());
+    }
+
+
+    #[test]
+    fn test_parse_errors_have_line_numbers() {
+        let error = parse_stop_times("trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+                                      t,08:00:00,08:00:00,A,1\n\
+                                      t,8am,08:00:00,B,2\n").unwrap_err();
+        assert_eq!("stop_times.txt line 3: Invalid time '8am'", error.to_string());
+
+        let error = parse_stops("stop_id,stop_name\nA,Somewhere\n").unwrap_err();
+        assert_eq!("stops.txt has no stop_lat column", error.to_string());
+    }
+
+
+    #[test]
+    fn test_parse_csv_line() {
+        assert_eq!(vec!["a", "b, c", "say \"hi\"", ""], parse_csv_line(r#"a,"b, c","say ""hi""","#));
+    }
+
+
+    #[test]
+    fn test_service_days() {
+        assert_eq!(0, weekday(20240101));
+        assert_eq!(3, weekday(19700101));
+        assert_eq!(6, weekday(20000305));
+        assert_eq!(20231231, previous_date(20240101));
+        assert_eq!(20240229, previous_date(20240301));
+        assert_eq!(20240301, previous_date(20240302));
+
+        let feed = read_gtfs(fixture_path().to_str().unwrap()).unwrap();
+        assert!(feed.service_runs_on("weekdays", 20240102));
+        assert!(!feed.service_runs_on("weekdays", 20240106));
+        assert!(!feed.service_runs_on("weekdays", 20250102));
+        // Removed by an exception.
+        assert!(!feed.service_runs_on("weekdays", 20240103));
+    }
+}
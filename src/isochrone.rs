@@ -0,0 +1,128 @@
+use crate::geo_utils::Location;
+use crate::road_network::{Cost, RoadNetwork, NodeIndex};
+use crate::shortest_path::{bounded_shortest_path_tree, Direction};
+
+use std::collections::HashMap;
+
+
+/// A point part way along an edge where the time budget of an isochrone runs out.
+#[derive(Debug, PartialEq)]
+pub struct BoundaryPoint {
+    pub from: NodeIndex,
+    pub to: NodeIndex,
+    /// How far along the edge the point is, between 0 and 1.
+    pub fraction: f64,
+    pub location: Location,
+}
+
+
+/// Every node reachable from the source within the budget, with the cost of reaching it.
+pub fn isochrone(network: &RoadNetwork, source: NodeIndex, budget: Cost) -> HashMap<NodeIndex, Cost> {
+    if network.get_node(source).is_none() {
+        return HashMap::new();
+    }
+    bounded_shortest_path_tree(network, source, Direction::Forward, budget).costs
+}
+
+
+/// The points where the budget runs out on edges leaving the isochrone.
+///
+/// This includes edges between two reached nodes which can't be driven to the end within
+/// the budget, so a road reached from both ends has a point from each.
+///
+/// Locations are interpolated linearly between the nodes at either end of the edge,
+/// assuming a constant speed along it.
+pub fn isochrone_boundary(network: &RoadNetwork, source: NodeIndex, budget: Cost) -> Vec<BoundaryPoint> {
+    let costs = isochrone(network, source, budget);
+
+    let mut boundary = Vec::new();
+    for (node_index, cost) in costs.iter() {
+        let node = network.get_node(*node_index).unwrap();
+        for edge in node.neighbours.iter() {
+            if cost + edge.cost <= budget {
+                continue;
+            }
+
+            let fraction = (budget - cost) as f64 / edge.cost as f64;
+            let destination = &network.get_node(edge.destination).unwrap().location;
+            let location = Location::new(
+                node.location.lat() + fraction * (destination.lat() - node.location.lat()),
+                node.location.lng() + fraction * (destination.lng() - node.location.lng()),
+            );
+
+            boundary.push(BoundaryPoint {from: *node_index, to: edge.destination, fraction, location});
+        }
+    }
+
+    boundary.sort_by_key(|point| (point.from, point.to));
+    boundary
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::isochrone::*;
+    use crate::road_network::Node;
+
+
+    fn get_test_network() -> RoadNetwork {
+        let mut network = RoadNetwork::new();
+
+        network.add_node(Node::new(1, Location::new(0., 0.))).unwrap();
+        network.add_node(Node::new(2, Location::new(0., 1.))).unwrap();
+        network.add_node(Node::new(3, Location::new(0., 2.))).unwrap();
+        network.add_node(Node::new(4, Location::new(1., 0.))).unwrap();
+
+        network.add_edge(1, 2, 10);
+        network.add_edge(2, 3, 10);
+        network.add_edge(1, 4, 40);
+        network.add_edge(4, 1, 40);
+
+        network
+    }
+
+
+    #[test]
+    fn test_isochrone_nodes() {
+        let network = get_test_network();
+
+        let nodes = isochrone(&network, 1, 15);
+        assert_eq!(2, nodes.len());
+        assert_eq!(Some(&0), nodes.get(&1));
+        assert_eq!(Some(&10), nodes.get(&2));
+
+        assert_eq!(4, isochrone(&network, 1, 40).len());
+        assert!(isochrone(&network, 99, 40).is_empty());
+    }
+
+
+    #[test]
+    fn test_isochrone_boundary() {
+        let network = get_test_network();
+        let boundary = isochrone_boundary(&network, 1, 15);
+
+        assert_eq!(2, boundary.len());
+
+        assert_eq!((1, 4), (boundary[0].from, boundary[0].to));
+        assert_eq!(15. / 40., boundary[0].fraction);
+        assert_eq!(Location::new(15. / 40., 0.), boundary[0].location);
+
+        assert_eq!((2, 3), (boundary[1].from, boundary[1].to));
+        assert_eq!(0.5, boundary[1].fraction);
+        assert_eq!(Location::new(0., 1.5), boundary[1].location);
+    }
+
+
+    #[test]
+    fn test_edge_reached_from_both_ends() {
+        let mut network = get_test_network();
+        network.add_node(Node::new(5, Location::new(1., 1.))).unwrap();
+        network.add_edge(1, 5, 10);
+        network.add_edge(2, 5, 30);
+        network.add_edge(5, 2, 30);
+
+        let boundary = isochrone_boundary(&network, 1, 15);
+        let points: Vec<_> = boundary.iter().map(|point| (point.from, point.to, point.fraction)).collect();
+        assert_eq!(vec![(1, 4, 15. / 40.), (2, 3, 0.5), (2, 5, 5. / 30.), (5, 2, 5. / 30.)], points);
+    }
+}
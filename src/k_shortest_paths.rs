@@ -0,0 +1,179 @@
+use crate::potential::ZeroPotential;
+use crate::road_network::{RoadNetwork, Node, NodeIndex};
+use crate::shortest_path::{filtered_shortest_path, ShortestPath};
+
+use std::collections::HashSet;
+
+
+/// Find up to `k` loopless paths from the start to the end in order of increasing cost,
+/// using Yen's algorithm.
+///
+/// Each candidate path branches off one of the paths already found at a spur node. The
+/// rest of the candidate is found by a search which avoids the nodes before the spur
+/// node and the edges already used to leave it, without modifying the network. The
+/// statistics of each path add up every search run until it was found.
+pub fn k_shortest_paths(network: &RoadNetwork,
+                        start_node: &Node,
+                        end_node: &Node,
+                        k: usize,
+) -> Vec<ShortestPath> {
+    let mut paths: Vec<ShortestPath> = Vec::with_capacity(k);
+    if k == 0 {
+        return paths;
+    }
+    match filtered_shortest_path(network, start_node, end_node, &mut ZeroPotential, |_node, _edge_index| true) {
+        Some(path) => paths.push(path),
+        None => return paths,
+    }
+
+    let mut candidates: Vec<ShortestPath> = Vec::new();
+    let mut statistics = paths[0].statistics.unwrap_or_default();
+
+    while paths.len() < k {
+        let previous_path = paths.last().unwrap().path.clone();
+        let mut root_cost = 0;
+
+        for spur_index in 0..previous_path.len() - 1 {
+            let spur_node = previous_path[spur_index];
+            let root_path = &previous_path[..=spur_index];
+
+            let forbidden_edges: HashSet<(NodeIndex, NodeIndex)> = paths.iter()
+                .filter(|path| path.path.len() > spur_index + 1 && path.path[..=spur_index] == *root_path)
+                .map(|path| (spur_node, path.path[spur_index + 1]))
+                .collect();
+            let forbidden_nodes: HashSet<NodeIndex> = root_path[..spur_index].iter().cloned().collect();
+
+            let spur_path = filtered_shortest_path(
+                network,
+                network.get_node(spur_node).unwrap(),
+                end_node,
+                &mut ZeroPotential,
+                |node, edge_index| {
+                    let destination = node.neighbours[edge_index].destination;
+                    !forbidden_nodes.contains(&destination) && !forbidden_edges.contains(&(node.id, destination))
+                });
+
+            if let Some(spur_path) = spur_path {
+                if let Some(spur_statistics) = spur_path.statistics() {
+                    statistics.add(spur_statistics);
+                }
+                let mut path = root_path[..spur_index].to_vec();
+                path.extend(spur_path.path);
+                let candidate = ShortestPath {
+                    cost: root_cost + spur_path.cost,
+                    path,
+                    statistics: None,
+                };
+
+                if !candidates.contains(&candidate) && !paths.contains(&candidate) {
+                    candidates.push(candidate);
+                }
+            }
+
+            root_cost += network.edge_cost(spur_node, previous_path[spur_index + 1]).unwrap();
+        }
+
+        let best = candidates.iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| (a.cost, &a.path).cmp(&(b.cost, &b.path)))
+            .map(|(index, _)| index);
+        match best {
+            Some(index) => {
+                let mut path = candidates.swap_remove(index);
+                path.statistics = Some(statistics);
+                paths.push(path);
+            },
+            None => break,
+        }
+    }
+
+    paths
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::k_shortest_paths::*;
+    use crate::geo_utils::Location;
+    use crate::test_utils::build_grid_network;
+
+
+    fn get_test_network() -> RoadNetwork {
+        let mut network = RoadNetwork::new();
+        for i in 1..7 {
+            network.add_node(Node::new(i, Location::new(0., 0.))).unwrap();
+        }
+
+        // The classic example from Yen's algorithm's Wikipedia page, C to H.
+        network.add_edge(1, 2, 3);
+        network.add_edge(1, 3, 2);
+        network.add_edge(2, 4, 4);
+        network.add_edge(3, 2, 1);
+        network.add_edge(3, 4, 2);
+        network.add_edge(3, 5, 3);
+        network.add_edge(4, 5, 2);
+        network.add_edge(4, 6, 1);
+        network.add_edge(5, 6, 2);
+
+        network
+    }
+
+
+    #[test]
+    fn test_k_shortest_paths() {
+        let network = get_test_network();
+        let start = network.get_node(1).unwrap();
+        let end = network.get_node(6).unwrap();
+
+        let paths = k_shortest_paths(&network, start, end, 3);
+        assert_eq!(3, paths.len());
+
+        assert_eq!(5, paths[0].cost);
+        assert_eq!(vec![1, 3, 4, 6], paths[0].path);
+        assert_eq!(7, paths[1].cost);
+        assert_eq!(vec![1, 3, 5, 6], paths[1].path);
+        assert_eq!(8, paths[2].cost);
+        assert_eq!(vec![1, 2, 4, 6], paths[2].path);
+
+        // Later paths count the spur searches run to find them as well as the first search.
+        let settled: Vec<_> = paths.iter().map(|path| path.statistics().unwrap().settled_nodes).collect();
+        assert!(settled[0] < settled[1] && settled[1] < settled[2], "{:?}", settled);
+    }
+
+
+    #[test]
+    fn test_fewer_paths_than_requested() {
+        let network = get_test_network();
+        let start = network.get_node(3).unwrap();
+        let end = network.get_node(5).unwrap();
+
+        let paths = k_shortest_paths(&network, start, end, 10);
+        assert_eq!(3, paths.len());
+        assert_eq!(vec![3, 5], paths[0].path);
+
+        let unreachable = network.get_node(1).unwrap();
+        assert!(k_shortest_paths(&network, start, unreachable, 10).is_empty());
+    }
+
+
+    #[test]
+    fn test_paths_are_loopless_and_ordered() {
+        let network = build_grid_network(4, 4);
+        let start = network.get_node(0).unwrap();
+        let end = network.get_node(15).unwrap();
+
+        let paths = k_shortest_paths(&network, start, end, 15);
+        assert_eq!(15, paths.len());
+
+        for (a, b) in paths.iter().zip(paths.iter().skip(1)) {
+            assert!(a.cost <= b.cost);
+            assert_ne!(a.path, b.path);
+        }
+        for path in paths.iter() {
+            let mut nodes = path.path.clone();
+            nodes.sort();
+            nodes.dedup();
+            assert_eq!(path.path.len(), nodes.len());
+        }
+    }
+}
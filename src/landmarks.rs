@@ -0,0 +1,370 @@
+use crate::potential::Potential;
+use crate::road_network::{Cost, RoadNetwork, Node, NodeIndex};
+use crate::shortest_path::{shortest_path, shortest_path_tree, Direction, ShortestPath};
+
+use std::collections::HashMap;
+
+
+type LandmarkCosts = [Option<Cost>];
+
+
+/// Strategies for choosing which nodes of the network become landmarks.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LandmarkSelection {
+    /// Pick landmarks uniformly at random.
+    Random,
+    /// Greedily pick the node furthest from the landmarks chosen so far.
+    Farthest,
+    /// Pick leaves of a shortest path tree whose subtrees are poorly covered by
+    /// the landmarks chosen so far (Goldberg & Harrelson's avoid heuristic).
+    Avoid,
+}
+
+
+/// Distances to and from a set of landmarks, used to bound travel times via the
+/// triangle inequality.
+pub struct Landmarks {
+    landmarks: Vec<NodeIndex>,
+    /// For each node, the cost from each landmark to that node.
+    costs_from_landmarks: HashMap<NodeIndex, Vec<Option<Cost>>>,
+    /// For each node, the cost from that node to each landmark.
+    costs_to_landmarks: HashMap<NodeIndex, Vec<Option<Cost>>>,
+}
+
+
+impl Landmarks {
+
+    /// Select `num_landmarks` landmarks and compute the distance tables for them.
+    ///
+    /// The seed makes the random choices of every selection strategy reproducible.
+    pub fn new(network: &RoadNetwork,
+               num_landmarks: usize,
+               selection: LandmarkSelection,
+               seed: u64) -> Self {
+        let mut landmarks = Landmarks {
+            landmarks: Vec::with_capacity(num_landmarks),
+            costs_from_landmarks: HashMap::with_capacity(network.num_nodes()),
+            costs_to_landmarks: HashMap::with_capacity(network.num_nodes()),
+        };
+
+        let mut node_ids: Vec<_> = network.nodes_iter().map(|(id, _)| *id).collect();
+        node_ids.sort();
+        let mut rng = XorShiftRng::new(seed);
+
+        while landmarks.landmarks.len() < num_landmarks.min(node_ids.len()) {
+            let landmark = match selection {
+                LandmarkSelection::Random => None,
+                LandmarkSelection::Farthest => landmarks.farthest_node(&node_ids, &mut rng),
+                LandmarkSelection::Avoid => landmarks.avoid_node(network, &node_ids, &mut rng),
+            };
+            let landmark = landmark.unwrap_or_else(|| landmarks.random_node(&node_ids, &mut rng));
+            landmarks.add_landmark(network, landmark);
+        }
+
+        landmarks
+    }
+
+
+    pub fn landmarks(&self) -> &[NodeIndex] {
+        &self.landmarks
+    }
+
+
+    /// A lower bound on the cost of travelling from one node to another.
+    pub fn lower_bound(&self, from: NodeIndex, to: NodeIndex) -> Cost {
+        let (from_landmarks_to_target, target_to_landmarks) = match self.target_costs(to) {
+            Some(costs) => costs,
+            None => return 0,
+        };
+        self.lower_bound_to_target(from, from_landmarks_to_target, target_to_landmarks)
+    }
+
+
+    fn target_costs(&self, target: NodeIndex) -> Option<(&LandmarkCosts, &LandmarkCosts)> {
+        let from_landmarks = self.costs_from_landmarks.get(&target)?;
+        let to_landmarks = self.costs_to_landmarks.get(&target)?;
+        Some((from_landmarks, to_landmarks))
+    }
+
+
+    fn lower_bound_to_target(&self,
+                             from: NodeIndex,
+                             from_landmarks_to_target: &LandmarkCosts,
+                             target_to_landmarks: &LandmarkCosts) -> Cost {
+        let from_landmarks = match self.costs_from_landmarks.get(&from) {
+            Some(costs) => costs,
+            None => return 0,
+        };
+        let to_landmarks = &self.costs_to_landmarks[&from];
+
+        let mut bound = 0;
+        for i in 0..self.landmarks.len() {
+            // d(v, t) >= d(v, L) - d(t, L)
+            if let (Some(node_to_landmark), Some(target_to_landmark)) = (to_landmarks[i], target_to_landmarks[i]) {
+                bound = bound.max(node_to_landmark.saturating_sub(target_to_landmark));
+            }
+            // d(v, t) >= d(L, t) - d(L, v)
+            if let (Some(landmark_to_target), Some(landmark_to_node)) = (from_landmarks_to_target[i], from_landmarks[i]) {
+                bound = bound.max(landmark_to_target.saturating_sub(landmark_to_node));
+            }
+        }
+        bound
+    }
+
+
+    fn add_landmark(&mut self, network: &RoadNetwork, landmark: NodeIndex) {
+        let forward = shortest_path_tree(network, landmark, Direction::Forward).costs;
+        let backward = shortest_path_tree(network, landmark, Direction::Backward).costs;
+
+        for (node_index, _) in network.nodes_iter() {
+            self.costs_from_landmarks
+                .entry(*node_index)
+                .or_default()
+                .push(forward.get(node_index).cloned());
+            self.costs_to_landmarks
+                .entry(*node_index)
+                .or_default()
+                .push(backward.get(node_index).cloned());
+        }
+        self.landmarks.push(landmark);
+    }
+
+
+    fn random_node(&self, node_ids: &[NodeIndex], rng: &mut XorShiftRng) -> NodeIndex {
+        loop {
+            let node_index = node_ids[rng.next_below(node_ids.len())];
+            if !self.landmarks.contains(&node_index) {
+                return node_index;
+            }
+        }
+    }
+
+
+    /// The node whose closest landmark is as far away as possible.
+    ///
+    /// The first landmark is chosen at random.
+    fn farthest_node(&self, node_ids: &[NodeIndex], rng: &mut XorShiftRng) -> Option<NodeIndex> {
+        if self.landmarks.is_empty() {
+            return None;
+        }
+
+        node_ids.iter()
+            .filter(|node_index| !self.landmarks.contains(node_index))
+            .filter_map(|node_index| {
+                let closest = self.costs_from_landmarks[node_index]
+                    .iter()
+                    .zip(self.costs_to_landmarks[node_index].iter())
+                    .filter_map(|(from, to)| Some((*from)? + (*to)?))
+                    .min()?;
+                Some((closest, *node_index))
+            })
+            .max()
+            .map(|(_, node_index)| node_index)
+            .or_else(|| Some(self.random_node(node_ids, rng)))
+    }
+
+
+    /// Grow a shortest path tree from a random root and descend into the subtree
+    /// where the current landmarks give the worst lower bounds for as long as possible.
+    fn avoid_node(&self,
+                  network: &RoadNetwork,
+                  node_ids: &[NodeIndex],
+                  rng: &mut XorShiftRng) -> Option<NodeIndex> {
+        let root = self.random_node(node_ids, rng);
+        let tree = shortest_path_tree(network, root, Direction::Forward);
+
+        let mut children: HashMap<NodeIndex, Vec<NodeIndex>> = HashMap::new();
+        for (node_index, parent) in tree.parents.iter() {
+            if let Some(parent) = parent {
+                children.entry(*parent).or_default().push(*node_index);
+            }
+        }
+
+        // Visit the tree in pre-order, then accumulate subtree sizes from the leaves upwards.
+        // A subtree containing a landmark is already well covered so has no size.
+        let mut pre_order = Vec::with_capacity(tree.costs.len());
+        let mut stack = vec![root];
+        while let Some(node_index) = stack.pop() {
+            pre_order.push(node_index);
+            if let Some(node_children) = children.get(&node_index) {
+                stack.extend(node_children);
+            }
+        }
+
+        let mut sizes: HashMap<NodeIndex, Option<Cost>> = HashMap::with_capacity(pre_order.len());
+        for &node_index in pre_order.iter().rev() {
+            let cost = tree.costs[&node_index];
+            let size = if self.landmarks.contains(&node_index) {
+                None
+            } else {
+                let weight = cost - self.lower_bound(root, node_index).min(cost);
+                children.get(&node_index)
+                    .map_or(Some(weight), |children| {
+                        children.iter().try_fold(weight, |total, child| Some(total + sizes[child]?))
+                    })
+            };
+            sizes.insert(node_index, size);
+        }
+
+        let mut current = root;
+        loop {
+            let next = children.get(&current).and_then(|children| {
+                children.iter()
+                    .filter_map(|child| sizes[child].map(|size| (size, *child)))
+                    .filter(|&(size, _)| size > 0)
+                    .max()
+            });
+            match next {
+                Some((_, child)) => current = child,
+                None => break,
+            }
+        }
+
+        if current == root || self.landmarks.contains(&current) {
+            None
+        } else {
+            Some(current)
+        }
+    }
+}
+
+
+/// The landmark lower bounds to the target as a potential.
+///
+/// The target's rows of the distance tables are looked up once per query.
+pub struct LandmarkPotential<'a> {
+    landmarks: &'a Landmarks,
+    target_costs: Option<(&'a LandmarkCosts, &'a LandmarkCosts)>,
+}
+
+
+impl<'a> LandmarkPotential<'a> {
+    pub fn new(landmarks: &'a Landmarks) -> Self {
+        LandmarkPotential {landmarks, target_costs: None}
+    }
+}
+
+
+impl<'a> Potential for LandmarkPotential<'a> {
+    fn init(&mut self, target: &Node) {
+        self.target_costs = self.landmarks.target_costs(target.id);
+    }
+
+    fn potential(&self, node: &Node) -> Cost {
+        match self.target_costs {
+            Some((from_landmarks_to_target, target_to_landmarks)) => {
+                self.landmarks.lower_bound_to_target(node.id, from_landmarks_to_target, target_to_landmarks)
+            },
+            None => 0,
+        }
+    }
+}
+
+
+/// Answer a shortest path query using A* with the landmark lower bounds as the potential.
+pub fn alt_shortest_path(network: &RoadNetwork,
+                         landmarks: &Landmarks,
+                         start_node: &Node,
+                         end_node: &Node,
+) -> Option<ShortestPath> {
+    shortest_path(network, start_node, end_node, &mut LandmarkPotential::new(landmarks))
+}
+
+
+/// A small xorshift generator so that landmark selection is reproducible
+/// without pulling in an external crate.
+struct XorShiftRng {
+    state: u64,
+}
+
+
+impl XorShiftRng {
+    fn new(seed: u64) -> Self {
+        // The generator is stuck at zero, so nudge it away from there.
+        XorShiftRng {state: seed ^ 0x9E37_79B9_7F4A_7C15}
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    fn next_below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::landmarks::*;
+    use crate::shortest_path::dijkstra_shortest_path;
+    use crate::test_utils::build_grid_network;
+
+
+    fn check_alt_matches_dijkstra(selection: LandmarkSelection) {
+        let network = build_grid_network(5, 5);
+        let landmarks = Landmarks::new(&network, 3, selection, 42);
+        assert_eq!(3, landmarks.landmarks().len());
+
+        for start_index in 0..25 {
+            for end_index in 0..25 {
+                let start = network.get_node(start_index).unwrap();
+                let end = network.get_node(end_index).unwrap();
+
+                let dijkstra = dijkstra_shortest_path(&network, start, end).unwrap();
+                assert!(landmarks.lower_bound(start_index, end_index) <= dijkstra.cost);
+
+                let alt = alt_shortest_path(&network, &landmarks, start, end).unwrap();
+                assert_eq!(dijkstra.cost, alt.cost);
+            }
+        }
+    }
+
+
+    #[test]
+    fn test_random_landmarks() {
+        check_alt_matches_dijkstra(LandmarkSelection::Random);
+    }
+
+
+    #[test]
+    fn test_farthest_landmarks() {
+        check_alt_matches_dijkstra(LandmarkSelection::Farthest);
+    }
+
+
+    #[test]
+    fn test_avoid_landmarks() {
+        check_alt_matches_dijkstra(LandmarkSelection::Avoid);
+    }
+
+
+    #[test]
+    fn test_landmarks_are_distinct() {
+        let network = build_grid_network(3, 3);
+        let landmarks = Landmarks::new(&network, 20, LandmarkSelection::Farthest, 0);
+
+        let mut selected = landmarks.landmarks().to_vec();
+        selected.sort();
+        selected.dedup();
+        assert_eq!(9, selected.len());
+    }
+
+
+    #[test]
+    fn test_lower_bound_is_exact_at_landmark() {
+        let network = build_grid_network(4, 4);
+        let landmarks = Landmarks::new(&network, 1, LandmarkSelection::Random, 7);
+        let landmark = landmarks.landmarks()[0];
+
+        for end_index in 0..16 {
+            let start = network.get_node(landmark).unwrap();
+            let end = network.get_node(end_index).unwrap();
+            let cost = dijkstra_shortest_path(&network, start, end).unwrap().cost;
+            assert_eq!(cost, landmarks.lower_bound(landmark, end_index));
+        }
+    }
+}
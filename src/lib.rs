@@ -1,8 +1,35 @@
 #[macro_use] extern crate serde_derive;
 
+pub mod alternative_routes;
+pub mod arc_flags;
+pub mod bidirectional;
 pub mod connected_components;
+pub mod contraction_hierarchies;
+pub mod customizable_route_planning;
 pub mod geo_utils;
+pub mod gtfs;
+pub mod isochrone;
+pub mod k_shortest_paths;
+pub mod landmarks;
+pub mod map_matching;
+pub mod matrix;
 pub mod road_network;
 pub mod road_network_builder;
 pub mod osm_reader;
+pub mod pareto;
+pub mod partition;
+pub mod potential;
+pub mod query_engine;
 pub mod shortest_path;
+pub mod snapping;
+pub mod spatial_index;
+pub mod time_dependent;
+pub mod time_utils;
+pub mod tour;
+pub mod transit;
+pub mod turn_costs;
+pub mod turn_restrictions;
+pub mod via_route;
+
+#[cfg(test)]
+mod test_utils;
@@ -0,0 +1,519 @@
+extern crate serde_xml_rs;
+
+use self::serde_xml_rs::deserialize;
+
+use crate::geo_utils::{earth_distance, Location};
+use crate::osm_reader::de_from_str;
+use crate::road_network::{Cost, RoadNetwork, NodeIndex};
+use crate::snapping::{EdgeSnap, EdgeSnapper};
+use crate::time_utils::days_since_epoch;
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+
+
+/// A position from a GPS trace, with its time in seconds since 1970-01-01 if known.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GpsPoint {
+    pub location: Location,
+    pub time: Option<f64>,
+}
+
+
+impl GpsPoint {
+    pub fn new(lat: f64, lng: f64, time: Option<f64>) -> Self {
+        GpsPoint {location: Location::new(lat, lng), time}
+    }
+}
+
+
+/// Read a trace from a CSV file with a `time,lat,lng` line for each point.
+///
+/// The time is in seconds and may be left empty. A header line, empty lines and lines
+/// starting with `#` are skipped.
+pub fn read_gps_csv(file_name: &str) -> Result<Vec<GpsPoint>, Box<dyn Error>> {
+    let f = File::open(file_name)?;
+    parse_gps_csv(BufReader::new(f))
+}
+
+
+fn parse_gps_csv<R: BufRead>(reader: R) -> Result<Vec<GpsPoint>, Box<dyn Error>> {
+    let mut points = Vec::new();
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (points.is_empty() && line.contains("lat")) {
+            continue;
+        }
+        let point = parse_gps_line(line)
+            .map_err(|e| format!("Line {} of GPS trace: {}", line_number + 1, e))?;
+        points.push(point);
+    }
+    Ok(points)
+}
+
+
+fn parse_gps_line(line: &str) -> Result<GpsPoint, Box<dyn Error>> {
+    let fields: Vec<_> = line.split(',').map(|field| field.trim()).collect();
+    if fields.len() != 3 {
+        return Err(From::from(format!("Expected 3 fields but found {}", fields.len())));
+    }
+    let time = if fields[0].is_empty() { None } else { Some(fields[0].parse()?) };
+    Ok(GpsPoint::new(fields[1].parse()?, fields[2].parse()?, time))
+}
+
+
+#[derive(Debug, Deserialize)]
+struct Gpx {
+    #[serde(rename = "trk", default)]
+    tracks: Vec<GpxTrack>,
+}
+
+
+#[derive(Debug, Deserialize)]
+struct GpxTrack {
+    #[serde(rename = "trkseg", default)]
+    segments: Vec<GpxSegment>,
+}
+
+
+#[derive(Debug, Deserialize)]
+struct GpxSegment {
+    #[serde(rename = "trkpt", default)]
+    points: Vec<GpxPoint>,
+}
+
+
+#[derive(Debug, Deserialize)]
+struct GpxPoint {
+    #[serde(deserialize_with = "de_from_str")]
+    lat: f64,
+    #[serde(deserialize_with = "de_from_str")]
+    lon: f64,
+    #[serde(default)]
+    time: Option<String>,
+}
+
+
+/// Read the track points of every track in a GPX file, in order.
+pub fn read_gpx(file_name: &str) -> Result<Vec<GpsPoint>, Box<dyn Error>> {
+    let f = File::open(file_name)?;
+    parse_gpx(BufReader::new(f))
+}
+
+
+fn parse_gpx<R: Read>(reader: R) -> Result<Vec<GpsPoint>, Box<dyn Error>> {
+    let gpx: Gpx = match deserialize(reader) {
+        Ok(gpx) => gpx,
+        Err(e) => return Err(From::from(format!("{:?}", e))),
+    };
+    gpx.tracks.iter()
+        .flat_map(|track| track.segments.iter())
+        .flat_map(|segment| segment.points.iter())
+        .map(|point| {
+            let time = match point.time {
+                Some(ref time) => Some(parse_timestamp(time.trim())?),
+                None => None,
+            };
+            Ok(GpsPoint::new(point.lat, point.lon, time))
+        })
+        .collect()
+}
+
+
+/// Parse an ISO 8601 timestamp such as `2024-05-01T08:30:00Z` into seconds since
+/// 1970-01-01, allowing fractional seconds and an offset such as `+02:00`.
+fn parse_timestamp(timestamp: &str) -> Result<f64, Box<dyn Error>> {
+    let invalid = || format!("Invalid timestamp '{}'", timestamp);
+    let (date, time) = timestamp.split_once('T').ok_or_else(invalid)?;
+
+    let date: Vec<i64> = date.split('-').map(|part| part.parse()).collect::<Result<_, _>>()?;
+    if date.len() != 3 {
+        return Err(From::from(invalid()));
+    }
+
+    let (time, offset) = if let Some(time) = time.strip_suffix('Z') {
+        (time, 0.)
+    } else if let Some(position) = time.rfind(['+', '-']) {
+        let (time, offset) = time.split_at(position);
+        let (hours, minutes) = offset[1..].split_once(':').ok_or_else(invalid)?;
+        let sign = if offset.starts_with('-') { -1. } else { 1. };
+        (time, sign * (hours.parse::<f64>()? * 3600. + minutes.parse::<f64>()? * 60.))
+    } else {
+        (time, 0.)
+    };
+
+    let time: Vec<f64> = time.split(':').map(|part| part.parse()).collect::<Result<_, _>>()?;
+    if time.len() != 3 {
+        return Err(From::from(invalid()));
+    }
+
+    let days = days_since_epoch(date[0], date[1], date[2]) as f64;
+    Ok(days * 86400. + time[0] * 3600. + time[1] * 60. + time[2] - offset)
+}
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct MapMatchingParameters {
+    /// How far in metres from a GPS point an edge can be to be a candidate for it.
+    pub candidate_radius: f64,
+    /// The most candidates kept for a point, closest first.
+    pub max_candidates: usize,
+    /// The standard deviation in metres of the GPS measurements.
+    pub gps_sigma: f64,
+    /// How much in metres a route between two candidates can be expected to differ
+    /// from the great circle distance between their GPS points.
+    pub transition_beta: f64,
+    /// Routes between candidates longer than this multiple of the great circle distance
+    /// between their points, plus twice the candidate radius, aren't considered.
+    pub max_route_factor: f64,
+}
+
+
+impl Default for MapMatchingParameters {
+    fn default() -> Self {
+        MapMatchingParameters {
+            candidate_radius: 50.,
+            max_candidates: 8,
+            gps_sigma: 10.,
+            transition_beta: 10.,
+            max_route_factor: 2.,
+        }
+    }
+}
+
+
+/// The result of matching a trace to a network.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchedRoute {
+    pub(crate) points: Vec<Option<EdgeSnap>>,
+    pub(crate) paths: Vec<Vec<NodeIndex>>,
+}
+
+
+impl MatchedRoute {
+
+    /// The position on the network each GPS point was matched to, or None for points
+    /// with no edge nearby.
+    pub fn points(&self) -> &[Option<EdgeSnap>] {
+        &self.points
+    }
+
+    /// The nodes driven through, from the start of the first matched edge to the end of
+    /// the last. Where no route joins two consecutive points the trace is split and
+    /// there is a path for each part.
+    pub fn paths(&self) -> &[Vec<NodeIndex>] {
+        &self.paths
+    }
+}
+
+
+/// The route between two candidates, if one can be driven to the other.
+struct Transition {
+    length: f64,
+    /// The nodes from the end of the first edge to the start of the second, empty when
+    /// both are on the same edge.
+    nodes: Vec<NodeIndex>,
+}
+
+
+/// The best candidate of the previous point and the nodes of the route from it.
+type Predecessor = (usize, Vec<NodeIndex>);
+
+
+/// Match a GPS trace to the network with a hidden Markov model.
+///
+/// The candidates of each point are the edges near it, which are more likely the closer
+/// they are. Moving from one candidate to the next is more likely the closer the length
+/// of the route between them is to the great circle distance between the points. The
+/// most likely sequence of candidates is found with the Viterbi algorithm.
+pub fn match_trace(network: &RoadNetwork,
+                   snapper: &EdgeSnapper,
+                   trace: &[GpsPoint],
+                   parameters: &MapMatchingParameters,
+) -> MatchedRoute {
+    let candidates: Vec<Vec<EdgeSnap>> = trace.iter()
+        .map(|point| {
+            let mut candidates = snapper.candidates(
+                network, point.location.lat(), point.location.lng(), parameters.candidate_radius);
+            candidates.truncate(parameters.max_candidates);
+            candidates
+        })
+        .collect();
+
+    // For each point, the best predecessor of each candidate and the route from it.
+    let mut back: Vec<Vec<Option<Predecessor>>> = vec![Vec::new(); trace.len()];
+    let mut segments = Vec::new();
+    let mut previous: Option<(usize, Vec<f64>)> = None;
+
+    for (t, point_candidates) in candidates.iter().enumerate() {
+        if point_candidates.is_empty() {
+            continue;
+        }
+        let emissions: Vec<_> = point_candidates.iter()
+            .map(|candidate| -0.5 * (candidate.distance / parameters.gps_sigma).powi(2))
+            .collect();
+
+        let mut scores = vec![f64::NEG_INFINITY; point_candidates.len()];
+        back[t] = vec![None; point_candidates.len()];
+        if let Some((previous_t, ref previous_scores)) = previous {
+            let great_circle = earth_distance(&trace[previous_t].location, &trace[t].location);
+            let max_length = parameters.max_route_factor * great_circle + 2. * parameters.candidate_radius;
+            for (i, from) in candidates[previous_t].iter().enumerate() {
+                for (j, transition) in transitions(network, from, point_candidates, max_length).into_iter().enumerate() {
+                    let transition = match transition {
+                        Some(transition) => transition,
+                        None => continue,
+                    };
+                    let score = previous_scores[i] + emissions[j]
+                        - (transition.length - great_circle).abs() / parameters.transition_beta;
+                    if score > scores[j] {
+                        scores[j] = score;
+                        back[t][j] = Some((i, transition.nodes));
+                    }
+                }
+            }
+        }
+
+        if scores.iter().all(|score| *score == f64::NEG_INFINITY) {
+            // The first point, or no candidate can be reached, so start a new part.
+            if let Some((previous_t, previous_scores)) = previous.take() {
+                segments.push((previous_t, best_candidate(&previous_scores)));
+            }
+            scores = emissions;
+        }
+        previous = Some((t, scores));
+    }
+    if let Some((last_t, scores)) = previous {
+        segments.push((last_t, best_candidate(&scores)));
+    }
+
+    let mut points = vec![None; trace.len()];
+    let mut paths = Vec::new();
+    for (end_t, end_candidate) in segments {
+        let mut path = vec![candidates[end_t][end_candidate].to];
+        let (mut t, mut candidate) = (end_t, end_candidate);
+        loop {
+            let snap = candidates[t][candidate];
+            points[t] = Some(snap);
+            match back[t][candidate].take() {
+                Some((previous_candidate, nodes)) => {
+                    path.extend(nodes.into_iter().rev());
+                    t = (0..t).rev().find(|previous_t| !candidates[*previous_t].is_empty()).unwrap();
+                    candidate = previous_candidate;
+                },
+                None => {
+                    path.push(snap.from);
+                    break;
+                },
+            }
+        }
+        path.reverse();
+        path.dedup();
+        paths.push(path);
+    }
+
+    MatchedRoute {points, paths}
+}
+
+
+fn best_candidate(scores: &[f64]) -> usize {
+    (0..scores.len()).max_by(|a, b| scores[*a].total_cmp(&scores[*b])).unwrap()
+}
+
+
+/// The routes from a candidate to each candidate of the next point, following the
+/// quickest path between their edges as `dijkstra_shortest_path` would.
+///
+/// One search from the end of the candidate's edge finds the routes to all of them, and
+/// doesn't follow routes longer than `max_length` metres.
+fn transitions(network: &RoadNetwork, from: &EdgeSnap, to: &[EdgeSnap], max_length: f64) -> Vec<Option<Transition>> {
+    let start_length = (1. - from.fraction) * edge_length(network, from.from, from.to);
+    let mut targets: HashSet<_> = to.iter().map(|snap| snap.from).collect();
+
+    // The cost, length and parent of every node reached, and of those settled.
+    let mut reached: HashMap<NodeIndex, (Cost, f64, Option<NodeIndex>)> = HashMap::new();
+    let mut settled: HashMap<NodeIndex, (f64, Option<NodeIndex>)> = HashMap::new();
+    let mut heap = BinaryHeap::new();
+    reached.insert(from.to, (0, start_length, None));
+    heap.push(Reverse((0, from.to)));
+
+    while let Some(Reverse((cost, node_index))) = heap.pop() {
+        if settled.contains_key(&node_index) {
+            continue;
+        }
+        let (_, length, parent) = reached[&node_index];
+        settled.insert(node_index, (length, parent));
+        targets.remove(&node_index);
+        if targets.is_empty() {
+            break;
+        }
+
+        for edge in network.get_node(node_index).unwrap().neighbours.iter() {
+            let next_length = length + edge_length(network, node_index, edge.destination);
+            let next_cost = cost + edge.cost;
+            if next_length > max_length || settled.contains_key(&edge.destination) {
+                continue;
+            }
+            if reached.get(&edge.destination).is_none_or(|(best_cost, _, _)| next_cost < *best_cost) {
+                reached.insert(edge.destination, (next_cost, next_length, Some(node_index)));
+                heap.push(Reverse((next_cost, edge.destination)));
+            }
+        }
+    }
+
+    to.iter()
+        .map(|snap| {
+            if (from.from, from.to) == (snap.from, snap.to) && snap.fraction >= from.fraction {
+                let length = (snap.fraction - from.fraction) * edge_length(network, from.from, from.to);
+                return Some(Transition {length, nodes: Vec::new()});
+            }
+            let length = settled.get(&snap.from)?.0 + snap.fraction * edge_length(network, snap.from, snap.to);
+            if length > max_length {
+                return None;
+            }
+            let mut nodes = vec![snap.from];
+            while let Some(parent) = settled[&nodes[nodes.len() - 1]].1 {
+                nodes.push(parent);
+            }
+            nodes.reverse();
+            Some(Transition {length, nodes})
+        })
+        .collect()
+}
+
+
+fn edge_length(network: &RoadNetwork, from: NodeIndex, to: NodeIndex) -> f64 {
+    earth_distance(&network.get_node(from).unwrap().location, &network.get_node(to).unwrap().location)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::map_matching::*;
+    use crate::osm_reader::HighwayType;
+    use crate::road_network::Node;
+    use crate::test_utils::{add_road, build_grid_network};
+
+    use std::path::Path;
+
+
+    /// Points about 10 metres off the road along the bottom row of the grid from node 0
+    /// to 4, then up the right hand column to between 9 and 14.
+    fn noisy_trace() -> Vec<GpsPoint> {
+        vec![
+            GpsPoint::new(49.00008, 7.0007, Some(0.)),
+            GpsPoint::new(48.99992, 7.0021, Some(10.)),
+            GpsPoint::new(49.00008, 7.0035, Some(20.)),
+            GpsPoint::new(48.99992, 7.0049, Some(30.)),
+            GpsPoint::new(49.0005, 7.00573, Some(40.)),
+            GpsPoint::new(49.0015, 7.00547, Some(50.)),
+        ]
+    }
+
+
+    #[test]
+    fn test_parse_gps_csv() {
+        let csv = "time,lat,lng\n# A comment\n0,49.0,7.0\n\n,49.001,7.002\n";
+        let points = parse_gps_csv(csv.as_bytes()).unwrap();
+        assert_eq!(vec![GpsPoint::new(49.0, 7.0, Some(0.)), GpsPoint::new(49.001, 7.002, None)], points);
+
+        assert!(parse_gps_csv("0,49.0\n".as_bytes()).is_err());
+        assert!(parse_gps_csv("0,49.0,7.0\nx,49.0,7.0\n".as_bytes()).is_err());
+    }
+
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(0., parse_timestamp("1970-01-01T00:00:00Z").unwrap());
+        assert_eq!(1714552200., parse_timestamp("2024-05-01T08:30:00Z").unwrap());
+        assert_eq!(1714552200.5, parse_timestamp("2024-05-01T10:30:00.5+02:00").unwrap());
+        assert_eq!(1714552200., parse_timestamp("2024-05-01T03:30:00-05:00").unwrap());
+        assert!(parse_timestamp("2024-05-01").is_err());
+        assert!(parse_timestamp("2024-05-01T08:30Z").is_err());
+    }
+
+
+    #[test]
+    fn test_read_gpx() {
+        let gpx_path = Path::new("tests").join("fixtures").join("trace.gpx");
+        let points = read_gpx(gpx_path.to_str().unwrap()).unwrap();
+        assert_eq!(noisy_trace().len(), points.len());
+        for (point, expected) in points.iter().zip(noisy_trace().iter()) {
+            assert_eq!(expected.location, point.location);
+            assert_eq!(expected.time.map(|time| time + 1714552200.), point.time);
+        }
+    }
+
+
+    #[test]
+    fn test_match_trace() {
+        let network = build_grid_network(5, 5);
+        let snapper = EdgeSnapper::new(&network);
+        let parameters = MapMatchingParameters {candidate_radius: 40., ..Default::default()};
+        let route = match_trace(&network, &snapper, &noisy_trace(), &parameters);
+
+        assert_eq!(vec![vec![0, 1, 2, 3, 4, 9, 14]], route.paths());
+        let edges: Vec<_> = route.points().iter()
+            .map(|point| point.map(|snap| (snap.from, snap.to)))
+            .collect();
+        let expected = vec![(0, 1), (1, 2), (2, 3), (3, 4), (4, 9), (9, 14)];
+        assert_eq!(expected.into_iter().map(Some).collect::<Vec<_>>(), edges);
+        assert!(route.points().iter().all(|point| point.unwrap().distance < 15.));
+    }
+
+
+    #[test]
+    fn test_points_away_from_the_network() {
+        let network = build_grid_network(5, 5);
+        let snapper = EdgeSnapper::new(&network);
+        let mut trace = noisy_trace();
+        trace.insert(2, GpsPoint::new(48.99, 7.0028, Some(15.)));
+        let route = match_trace(&network, &snapper, &trace, &MapMatchingParameters::default());
+
+        assert_eq!(None, route.points()[2]);
+        assert_eq!(7, route.points().len());
+        assert_eq!(vec![vec![0, 1, 2, 3, 4, 9, 14]], route.paths());
+
+        let route = match_trace(&network, &snapper, &[], &MapMatchingParameters::default());
+        assert!(route.points().is_empty());
+        assert!(route.paths().is_empty());
+    }
+
+
+    #[test]
+    fn test_routes_too_long_to_follow() {
+        let network = build_grid_network(5, 5);
+        let snapper = EdgeSnapper::new(&network);
+
+        // Every route between consecutive points is over 100 metres, but only 40 are allowed.
+        let parameters = MapMatchingParameters {candidate_radius: 20., max_route_factor: 0., ..Default::default()};
+        let route = match_trace(&network, &snapper, &noisy_trace(), &parameters);
+        assert_eq!(noisy_trace().len(), route.paths().len());
+        assert!(route.points().iter().all(|point| point.is_some()));
+    }
+
+
+    #[test]
+    fn test_unconnected_parts() {
+        // A separate road a kilometre north of the grid, with nothing joining the two.
+        let mut network = build_grid_network(2, 2);
+        network.add_node(Node::new(10, Location::new(49.01, 7.0))).unwrap();
+        network.add_node(Node::new(11, Location::new(49.01, 7.0014))).unwrap();
+        add_road(&mut network, 10, 11, HighwayType::Residential);
+
+        let trace = vec![
+            GpsPoint::new(49.0, 7.0003, None),
+            GpsPoint::new(49.0, 7.0011, None),
+            GpsPoint::new(49.01, 7.0003, None),
+            GpsPoint::new(49.01, 7.0011, None),
+        ];
+        let snapper = EdgeSnapper::new(&network);
+        let route = match_trace(&network, &snapper, &trace, &MapMatchingParameters::default());
+        assert_eq!(vec![vec![0, 1], vec![10, 11]], route.paths());
+        assert!(route.points().iter().all(|point| point.is_some()));
+    }
+}
@@ -0,0 +1,179 @@
+use crate::road_network::{Cost, RoadNetwork, NodeIndex};
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+
+/// Travel times from a list of sources to a list of targets.
+///
+/// Unreachable pairs have no cost.
+#[derive(Debug, Eq, PartialEq)]
+pub struct CostMatrix {
+    sources: Vec<NodeIndex>,
+    targets: Vec<NodeIndex>,
+    /// The costs in row major order, one row per source.
+    costs: Vec<Option<Cost>>,
+}
+
+
+impl CostMatrix {
+
+    pub(crate) fn new(sources: &[NodeIndex], targets: &[NodeIndex]) -> Self {
+        CostMatrix {
+            sources: sources.to_vec(),
+            targets: targets.to_vec(),
+            costs: vec![None; sources.len() * targets.len()],
+        }
+    }
+
+
+    pub fn sources(&self) -> &[NodeIndex] {
+        &self.sources
+    }
+
+
+    pub fn targets(&self) -> &[NodeIndex] {
+        &self.targets
+    }
+
+
+    /// The cost from the source at `source_index` to the target at `target_index`.
+    pub fn cost(&self, source_index: usize, target_index: usize) -> Option<Cost> {
+        self.costs[source_index * self.targets.len() + target_index]
+    }
+
+
+    /// The costs from the source at `source_index` to every target.
+    pub fn row(&self, source_index: usize) -> &[Option<Cost>] {
+        let start = source_index * self.targets.len();
+        &self.costs[start..start + self.targets.len()]
+    }
+
+
+    pub(crate) fn set_cost(&mut self, source_index: usize, target_index: usize, cost: Cost) {
+        let index = source_index * self.targets.len() + target_index;
+        if self.costs[index].is_none_or(|current| cost < current) {
+            self.costs[index] = Some(cost);
+        }
+    }
+}
+
+
+/// The costs from one source to each of the targets, from a single Dijkstra search
+/// which stops as soon as every target has been settled.
+pub fn one_to_many(network: &RoadNetwork, source: NodeIndex, targets: &[NodeIndex]) -> Vec<Option<Cost>> {
+    let mut matrix = CostMatrix::new(&[source], targets);
+    fill_row(network, &mut matrix, 0, &target_columns(targets));
+    matrix.costs
+}
+
+
+/// The costs between every source and every target, with one search per source.
+pub fn many_to_many(network: &RoadNetwork, sources: &[NodeIndex], targets: &[NodeIndex]) -> CostMatrix {
+    let mut matrix = CostMatrix::new(sources, targets);
+    let columns = target_columns(targets);
+    for source_index in 0..sources.len() {
+        fill_row(network, &mut matrix, source_index, &columns);
+    }
+    matrix
+}
+
+
+/// The columns of the matrix each target node appears in.
+fn target_columns(targets: &[NodeIndex]) -> HashMap<NodeIndex, Vec<usize>> {
+    let mut columns: HashMap<NodeIndex, Vec<usize>> = HashMap::with_capacity(targets.len());
+    for (target_index, target) in targets.iter().enumerate() {
+        columns.entry(*target).or_default().push(target_index);
+    }
+    columns
+}
+
+
+fn fill_row(network: &RoadNetwork,
+            matrix: &mut CostMatrix,
+            source_index: usize,
+            columns: &HashMap<NodeIndex, Vec<usize>>) {
+    let source = matrix.sources[source_index];
+    if network.get_node(source).is_none() {
+        return;
+    }
+
+    let mut heap = BinaryHeap::new();
+    let mut costs = HashMap::new();
+    let mut settled = HashSet::new();
+    let mut remaining_targets = columns.len();
+
+    costs.insert(source, 0);
+    heap.push(Reverse((0, source)));
+
+    while let Some(Reverse((cost, node_index))) = heap.pop() {
+        if !settled.insert(node_index) {
+            continue;
+        }
+
+        if let Some(target_indices) = columns.get(&node_index) {
+            for target_index in target_indices {
+                matrix.set_cost(source_index, *target_index, cost);
+            }
+            remaining_targets -= 1;
+            if remaining_targets == 0 {
+                break;
+            }
+        }
+
+        let node = network.get_node(node_index).unwrap();
+        for edge in node.neighbours.iter() {
+            let neighbour_cost = cost + edge.cost;
+            if costs.get(&edge.destination).is_none_or(|current| neighbour_cost < *current) {
+                costs.insert(edge.destination, neighbour_cost);
+                heap.push(Reverse((neighbour_cost, edge.destination)));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::matrix::*;
+    use crate::geo_utils::Location;
+    use crate::road_network::Node;
+    use crate::shortest_path::dijkstra_shortest_path;
+    use crate::test_utils::build_grid_network;
+
+
+    #[test]
+    fn test_many_to_many_matches_dijkstra() {
+        let network = build_grid_network(5, 5);
+        let sources = vec![0, 7, 12, 24];
+        let targets = vec![3, 12, 20, 0, 3];
+
+        let matrix = many_to_many(&network, &sources, &targets);
+        assert_eq!(&sources[..], matrix.sources());
+        assert_eq!(&targets[..], matrix.targets());
+
+        for (source_index, source) in sources.iter().enumerate() {
+            for (target_index, target) in targets.iter().enumerate() {
+                let start = network.get_node(*source).unwrap();
+                let end = network.get_node(*target).unwrap();
+                let cost = dijkstra_shortest_path(&network, start, end).map(|path| path.cost);
+                assert_eq!(cost, matrix.cost(source_index, target_index));
+            }
+        }
+    }
+
+
+    #[test]
+    fn test_unreachable_targets() {
+        let mut network = RoadNetwork::new();
+        for i in 1..4 {
+            network.add_node(Node::new(i, Location::new(0., 0.))).unwrap();
+        }
+        network.add_edge(1, 2, 5);
+        network.add_edge(2, 3, 10);
+
+        assert_eq!(vec![Some(0), Some(5), Some(15)], one_to_many(&network, 1, &[1, 2, 3]));
+        assert_eq!(vec![None, None, Some(0)], one_to_many(&network, 3, &[1, 2, 3]));
+        assert_eq!(vec![None], one_to_many(&network, 99, &[1]));
+    }
+}
@@ -32,6 +32,7 @@ pub enum HighwayType {
 
 impl HighwayType {
 
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
         use self::HighwayType::*;
         match s {
@@ -75,6 +76,16 @@ impl HighwayType {
         };
         kmh_to_ms(speed_kmh)
     }
+
+    /// The fastest speed of any highway type, used to bound travel times from below.
+    pub fn max_speed_ms() -> f64 {
+        use self::HighwayType::*;
+        [Motorway, Trunk, Primary, Secondary, Tertiary, MotorwayLink, TrunkLink, PrimaryLink,
+         SecondaryLink, Road, Unclassified, Residential, Unsurfaced, LivingStreet, Service]
+            .iter()
+            .map(|highway_type| highway_type.speed_ms())
+            .fold(0., f64::max)
+    }
 }
 
 fn kmh_to_ms(speed_in_kmh: f64) -> f64 {
@@ -113,7 +124,7 @@ pub struct OsmNode {
 #[derive(Debug, Deserialize)]
 pub struct OsmWay {
     #[serde(deserialize_with = "de_from_str")]
-    id: u64,
+    pub id: u64,
 
     #[serde(rename = "nd", default)]
     pub nodes: Vec<OsmNd>,
@@ -127,7 +138,7 @@ impl OsmWay {
 
     pub fn highway_type(&self) -> Option<HighwayType> {
         self.get_tag_value("highway")
-            .and_then(|tag_value| HighwayType::from_str(&tag_value))
+            .and_then(HighwayType::from_str)
     }
 
     fn get_tag_value(&self, key: &str) -> Option<&str> {
@@ -141,16 +152,62 @@ impl OsmWay {
 }
 
 
+#[derive(Debug, Deserialize)]
+pub struct OsmMember {
+    #[serde(rename = "type", default)]
+    pub type_: String,
+    #[serde(deserialize_with = "de_from_str")]
+    #[serde(rename = "ref", default)]
+    pub ref_: u64,
+    #[serde(default)]
+    pub role: String,
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct OsmRelation {
+    #[serde(deserialize_with = "de_from_str")]
+    pub id: u64,
+
+    #[serde(rename = "member", default)]
+    pub members: Vec<OsmMember>,
+
+    #[serde(rename = "tag", default)]
+    tags: Vec<OsmTag>,
+}
+
+
+impl OsmRelation {
+
+    /// The value of the `restriction` tag, such as `no_left_turn`.
+    pub fn restriction(&self) -> Option<&str> {
+        self.tags.iter()
+            .find(|tag| tag.key == "restriction")
+            .map(|tag| tag.value.as_str())
+    }
+
+    /// The ids of the members with the given role and type, in order.
+    pub fn member_refs(&self, role: &str, type_: &str) -> Vec<u64> {
+        self.members.iter()
+            .filter(|member| member.role == role && member.type_ == type_)
+            .map(|member| member.ref_)
+            .collect()
+    }
+}
+
+
 #[derive(Debug, Deserialize)]
 pub struct Osm {
     #[serde(rename = "node", default)]
     pub nodes: Vec<OsmNode>,
     #[serde(rename = "way", default)]
     pub ways: Vec<OsmWay>,
+    #[serde(rename = "relation", default)]
+    pub relations: Vec<OsmRelation>,
 }
 
 
-pub fn read_osm_extract(file_name: &str) -> Result<Osm, Box<dyn (::std::error::Error)>>  {
+pub fn read_osm_extract(file_name: &str) -> Result<Osm, Box<dyn ::std::error::Error>>  {
     let f = File::open(file_name)?;
     let reader = BufReader::new(&f);
     match deserialize(reader) {
@@ -160,7 +217,7 @@ pub fn read_osm_extract(file_name: &str) -> Result<Osm, Box<dyn (::std::error::E
 }
 
 
-fn de_from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+pub(crate) fn de_from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
     where D: Deserializer<'de>, T: FromStr, T::Err: Display
 {
     let s = <String>::deserialize(deserializer)?;
@@ -170,7 +227,7 @@ fn de_from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
 
 #[cfg(test)]
 mod tests {
-    use osm_reader::*;
+    use crate::osm_reader::*;
 
     use std::path::Path;
 
@@ -210,6 +267,27 @@ mod tests {
         assert_eq!(HighwayType::Unclassified, osm_way.highway_type().unwrap());
     }
 
+    #[test]
+    fn test_read_relation() {
+        let s = r##"
+        <relation id="62301">
+            <member type="way" ref="26659127" role="from"/>
+            <member type="node" ref="261728686" role="via"/>
+            <member type="way" ref="26659128" role="to"/>
+            <tag k="type" v="restriction"/>
+            <tag k="restriction" v="no_left_turn"/>
+        </relation>
+        "##;
+
+        let relation: OsmRelation = deserialize(s.as_bytes()).unwrap();
+        assert_eq!(62301, relation.id);
+        assert_eq!(3, relation.members.len());
+        assert_eq!(Some("no_left_turn"), relation.restriction());
+        assert_eq!(vec![26659127], relation.member_refs("from", "way"));
+        assert_eq!(vec![261728686], relation.member_refs("via", "node"));
+        assert!(relation.member_refs("via", "way").is_empty());
+    }
+
     #[test]
     fn test_read_osm() {
         let s = r##"
@@ -221,12 +299,16 @@ mod tests {
 	            <nd ref="470552"/>
                 <nd ref="470553"/>
             </way>
+            <relation id="1">
+                <member type="way" ref="26659127" role="from"/>
+            </relation>
         </osm>
         "##;
 
         let osm: Osm = deserialize(s.as_bytes()).unwrap();
         assert_eq!(2, osm.nodes.len());
         assert_eq!(1, osm.ways.len());
+        assert_eq!(1, osm.relations.len());
     }
 
     #[test]
@@ -0,0 +1,202 @@
+use crate::road_network::{Cost, Edge, RoadNetwork, Node, NodeIndex};
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+
+/// A cost of travelling along an edge, to be minimised alongside other criteria.
+///
+/// Costs must not be negative, so any criterion can be built from the edge itself, for
+/// example the length of motorway travelled by looking up which edges are motorways.
+pub type Criterion<'a> = &'a dyn Fn(&Edge) -> Cost;
+
+
+/// The travel time of an edge in seconds.
+pub fn travel_time(edge: &Edge) -> Cost {
+    edge.cost
+}
+
+
+/// The length of an edge in metres.
+pub fn distance(edge: &Edge) -> Cost {
+    edge.distance
+}
+
+
+/// A path which no other path beats on every criterion.
+#[derive(Debug, Eq, PartialEq)]
+pub struct ParetoPath {
+    costs: Vec<Cost>,
+    path: Vec<NodeIndex>,
+}
+
+
+impl ParetoPath {
+
+    /// The cost of the path for each criterion, in the order the criteria were given.
+    pub fn costs(&self) -> &[Cost] {
+        &self.costs
+    }
+
+
+    pub fn path(&self) -> &[NodeIndex] {
+        &self.path
+    }
+}
+
+
+/// A partial path ending at a node, with the label it extends.
+struct Label {
+    costs: Vec<Cost>,
+    node_index: NodeIndex,
+    parent: Option<usize>,
+}
+
+
+/// Whether costs `a` are at least as good as `b` on every criterion.
+fn dominates(a: &[Cost], b: &[Cost]) -> bool {
+    a.iter().zip(b.iter()).all(|(a, b)| a <= b)
+}
+
+
+/// Find every Pareto optimal path from the start to the end node for the criteria.
+///
+/// Labels are settled in lexicographic order of their costs, so a settled label is
+/// never dominated by one found later. A label is dropped when it's dominated by one
+/// already settled at its node or at the end node. Where several paths have exactly
+/// the same costs only one is returned. The paths are sorted by their costs.
+pub fn pareto_shortest_paths(network: &RoadNetwork,
+                             start_node: &Node,
+                             end_node: &Node,
+                             criteria: &[Criterion],
+) -> Vec<ParetoPath> {
+    let mut labels = vec![Label {costs: vec![0; criteria.len()], node_index: start_node.id, parent: None}];
+    let mut settled: HashMap<NodeIndex, Vec<usize>> = HashMap::new();
+    let mut heap = BinaryHeap::new();
+    heap.push(Reverse((labels[0].costs.clone(), 0)));
+
+    let is_dominated = |settled: &HashMap<NodeIndex, Vec<usize>>, labels: &[Label], node_index, costs: &[Cost]| {
+        [node_index, end_node.id].iter()
+            .filter_map(|node_index| settled.get(node_index))
+            .flatten()
+            .any(|label_index| dominates(&labels[*label_index].costs, costs))
+    };
+
+    while let Some(Reverse((costs, label_index))) = heap.pop() {
+        let node_index = labels[label_index].node_index;
+        if is_dominated(&settled, &labels, node_index, &costs) {
+            continue;
+        }
+        settled.entry(node_index).or_default().push(label_index);
+        if node_index == end_node.id {
+            continue;
+        }
+
+        let node = network.get_node(node_index).unwrap();
+        for edge in node.neighbours.iter() {
+            let new_costs: Vec<_> = costs.iter()
+                .zip(criteria.iter())
+                .map(|(cost, criterion)| cost + criterion(edge))
+                .collect();
+            if is_dominated(&settled, &labels, edge.destination, &new_costs) {
+                continue;
+            }
+            labels.push(Label {costs: new_costs.clone(), node_index: edge.destination, parent: Some(label_index)});
+            heap.push(Reverse((new_costs, labels.len() - 1)));
+        }
+    }
+
+    settled.get(&end_node.id)
+        .map(|end_labels| end_labels.iter()
+            .map(|label_index| {
+                let mut path = Vec::new();
+                let mut label = Some(*label_index);
+                while let Some(index) = label {
+                    path.push(labels[index].node_index);
+                    label = labels[index].parent;
+                }
+                path.reverse();
+                ParetoPath {costs: labels[*label_index].costs.clone(), path}
+            })
+            .collect())
+        .unwrap_or_default()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::pareto::*;
+    use crate::geo_utils::Location;
+    use crate::shortest_path::dijkstra_shortest_path;
+    use crate::test_utils::build_grid_network;
+
+
+    fn get_test_network() -> RoadNetwork {
+        let mut network = RoadNetwork::new();
+        for i in 1..7 {
+            network.add_node(Node::new(i, Location::new(0., 0.))).unwrap();
+        }
+
+        // A fast long way via 2, a slow short way via 3, a compromise via 5 and a
+        // direct road which is worse than the fast way on both counts.
+        network.add_edge_with_distance(1, 2, 10, 1000);
+        network.add_edge_with_distance(2, 4, 10, 1000);
+        network.add_edge_with_distance(1, 3, 30, 300);
+        network.add_edge_with_distance(3, 4, 30, 300);
+        network.add_edge_with_distance(1, 5, 25, 500);
+        network.add_edge_with_distance(5, 4, 25, 500);
+        network.add_edge_with_distance(1, 4, 100, 2000);
+
+        network
+    }
+
+
+    #[test]
+    fn test_pareto_front() {
+        let network = get_test_network();
+        let start = network.get_node(1).unwrap();
+        let end = network.get_node(4).unwrap();
+        let paths = pareto_shortest_paths(&network, start, end, &[&travel_time, &distance]);
+
+        let front: Vec<_> = paths.iter().map(|path| (path.costs().to_vec(), path.path().to_vec())).collect();
+        assert_eq!(vec![
+            (vec![20, 2000], vec![1, 2, 4]),
+            (vec![50, 1000], vec![1, 5, 4]),
+            (vec![60, 600], vec![1, 3, 4]),
+        ], front);
+    }
+
+
+    #[test]
+    fn test_custom_criterion() {
+        let network = get_test_network();
+        let start = network.get_node(1).unwrap();
+        let end = network.get_node(4).unwrap();
+        let edges = |_: &Edge| 1;
+        let paths = pareto_shortest_paths(&network, start, end, &[&edges, &distance]);
+
+        let front: Vec<_> = paths.iter().map(|path| path.costs().to_vec()).collect();
+        assert_eq!(vec![vec![1, 2000], vec![2, 600]], front);
+    }
+
+
+    #[test]
+    fn test_single_criterion_matches_dijkstra() {
+        let network = build_grid_network(4, 4);
+        let start = network.get_node(0).unwrap();
+        let end = network.get_node(15).unwrap();
+        let paths = pareto_shortest_paths(&network, start, end, &[&travel_time]);
+
+        assert_eq!(1, paths.len());
+        assert_eq!(dijkstra_shortest_path(&network, start, end).unwrap().cost, paths[0].costs()[0]);
+    }
+
+
+    #[test]
+    fn test_unreachable() {
+        let network = get_test_network();
+        let start = network.get_node(1).unwrap();
+        let end = network.get_node(6).unwrap();
+        assert!(pareto_shortest_paths(&network, start, end, &[&travel_time, &distance]).is_empty());
+    }
+}
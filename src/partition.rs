@@ -0,0 +1,538 @@
+use crate::road_network::{RoadNetwork, NodeIndex};
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fmt;
+use std::ops::Range;
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct InertialFlowParameters {
+    /// The number of directions to sort the nodes along, spread evenly over half a turn.
+    pub num_directions: usize,
+    /// The fraction of the nodes at each end of a direction which must end up on that
+    /// side of the cut, between 0 and 0.5. Larger values give more balanced cells.
+    pub balance: f64,
+}
+
+
+impl Default for InertialFlowParameters {
+    fn default() -> Self {
+        InertialFlowParameters {num_directions: 4, balance: 0.25}
+    }
+}
+
+
+/// Nodes split into two sides, with the number of roads between them.
+///
+/// A road with edges in both directions counts once.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Bisection {
+    pub first: Vec<NodeIndex>,
+    pub second: Vec<NodeIndex>,
+    pub cut_size: usize,
+}
+
+
+/// Split a set of nodes in two with inertial flow.
+///
+/// For each direction the nodes are sorted by the projection of their location onto
+/// it, and the smallest set of roads separating the first nodes from the last is found
+/// as a minimum cut with unit capacities. The smallest cut over all directions is kept,
+/// the more balanced one if there's a tie. Roads to nodes outside the set are ignored,
+/// and if the set isn't connected its components are shared between the sides instead,
+/// cutting only a component too large to leave whole.
+pub fn inertial_flow_bisection(network: &RoadNetwork,
+                               nodes: &[NodeIndex],
+                               parameters: &InertialFlowParameters,
+) -> Bisection {
+    let mut nodes = nodes.to_vec();
+    nodes.sort();
+    nodes.dedup();
+    if nodes.len() < 2 {
+        return Bisection {first: nodes, second: Vec::new(), cut_size: 0};
+    }
+
+    let graph = FlowGraph::new(network, &nodes);
+    let components = graph.components();
+    if components.len() > 1 {
+        let mut components: Vec<Vec<_>> = components.into_iter()
+            .map(|component| component.into_iter().map(|position| nodes[position]).collect())
+            .collect();
+        // Whole components can't be balanced if one of them holds most of the nodes, so
+        // that one is cut and the others make up the difference.
+        let mut sides = (Vec::new(), Vec::new());
+        let mut cut_size = 0;
+        if 2 * components[0].len() > nodes.len() {
+            let largest = inertial_flow_bisection(network, &components.remove(0), parameters);
+            sides = (largest.first, largest.second);
+            cut_size = largest.cut_size;
+        }
+        for component in components {
+            let side = if sides.0.len() <= sides.1.len() { &mut sides.0 } else { &mut sides.1 };
+            side.extend(component);
+        }
+        sides.0.sort();
+        sides.1.sort();
+        return Bisection {first: sides.0, second: sides.1, cut_size};
+    }
+
+    let mean_latitude = nodes.iter()
+        .map(|node_index| network.get_node(*node_index).unwrap().location.lat())
+        .sum::<f64>() / nodes.len() as f64;
+    let points: Vec<_> = nodes.iter()
+        .map(|node_index| {
+            let location = network.get_node(*node_index).unwrap().location;
+            (location.lng() * mean_latitude.to_radians().cos(), location.lat())
+        })
+        .collect();
+    let num_terminals = ((parameters.balance * nodes.len() as f64) as usize).clamp(1, nodes.len() / 2);
+
+    let mut best: Option<(usize, usize, Vec<bool>)> = None;
+    for direction in 0..parameters.num_directions.max(1) {
+        let angle = PI * direction as f64 / parameters.num_directions.max(1) as f64;
+        let projection = |point: &(f64, f64)| point.0 * angle.cos() + point.1 * angle.sin();
+        let mut order: Vec<_> = (0..nodes.len()).collect();
+        order.sort_by(|a, b| projection(&points[*a]).total_cmp(&projection(&points[*b])).then(a.cmp(b)));
+
+        let sources = &order[..num_terminals];
+        let sinks = &order[nodes.len() - num_terminals..];
+        let (cut_size, first_side) = graph.min_cut(sources, sinks);
+        let first_size = first_side.iter().filter(|in_first| **in_first).count();
+        let imbalance = first_size.abs_diff(nodes.len() - first_size);
+        if best.as_ref().is_none_or(|(best_cut, best_imbalance, _)| (cut_size, imbalance) < (*best_cut, *best_imbalance)) {
+            best = Some((cut_size, imbalance, first_side));
+        }
+    }
+
+    let (cut_size, _, first_side) = best.unwrap();
+    let (first, second): (Vec<_>, Vec<_>) = nodes.iter().zip(first_side.iter()).partition(|(_, in_first)| **in_first);
+    Bisection {
+        first: first.into_iter().map(|(node_index, _)| *node_index).collect(),
+        second: second.into_iter().map(|(node_index, _)| *node_index).collect(),
+        cut_size,
+    }
+}
+
+
+/// Divide the network into `2^depth` cells by bisecting it repeatedly with inertial flow,
+/// numbering the cells so those that were split from the same cell are next to each other.
+///
+/// Returns an error if that's more cells than the network has nodes.
+pub fn recursive_bisection(network: &RoadNetwork,
+                           depth: usize,
+                           parameters: &InertialFlowParameters,
+) -> Result<HashMap<NodeIndex, usize>, Box<dyn Error>> {
+    num_cells_at_depth(depth, network.num_nodes())?;
+
+    let mut cells = HashMap::with_capacity(network.num_nodes());
+    let nodes: Vec<_> = network.nodes_iter().map(|(node_index, _)| *node_index).collect();
+    let mut stack = vec![(nodes, depth, 0)];
+    while let Some((nodes, depth, cell)) = stack.pop() {
+        if depth == 0 {
+            cells.extend(nodes.into_iter().map(|node_index| (node_index, cell)));
+            continue;
+        }
+        let bisection = inertial_flow_bisection(network, &nodes, parameters);
+        stack.push((bisection.first, depth - 1, 2 * cell));
+        stack.push((bisection.second, depth - 1, 2 * cell + 1));
+    }
+    Ok(cells)
+}
+
+
+/// The `2^depth` cells given by splitting nodes in half `depth` times, or an error if
+/// that's more cells than there are nodes.
+pub(crate) fn num_cells_at_depth(depth: usize, num_nodes: usize) -> Result<usize, Box<dyn Error>> {
+    if depth == 0 {
+        return Ok(1);
+    }
+    if depth >= usize::BITS as usize || 1 << depth > num_nodes {
+        return Err(From::from(format!("A depth of {} gives more cells than the {} nodes", depth, num_nodes)));
+    }
+    Ok(1 << depth)
+}
+
+
+/// Sort nodes given as `(node, lat, lng)` along whichever of latitude and longitude they
+/// spread further in, so splitting them in the middle divides them at the median.
+pub(crate) fn sort_along_widest_dimension(nodes: &mut [(NodeIndex, f64, f64)]) {
+    let extent = |values: &mut dyn Iterator<Item=f64>| {
+        let (min, max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| {
+            (min.min(value), max.max(value))
+        });
+        max - min
+    };
+    let lat_extent = extent(&mut nodes.iter().map(|node| node.1));
+    let lng_extent = extent(&mut nodes.iter().map(|node| node.2));
+
+    if lat_extent >= lng_extent {
+        nodes.sort_by(|a, b| a.1.total_cmp(&b.1));
+    } else {
+        nodes.sort_by(|a, b| a.2.total_cmp(&b.2));
+    }
+}
+
+
+/// The roads of a set of nodes, with each road as two arcs which are each other's
+/// reverse, so flow can be pushed along it either way.
+struct FlowGraph {
+    /// The arcs of node `i` are `first_arcs[i]..first_arcs[i + 1]`.
+    first_arcs: Vec<usize>,
+    arc_targets: Vec<usize>,
+    reverse_arcs: Vec<usize>,
+}
+
+
+impl FlowGraph {
+
+    fn new(network: &RoadNetwork, nodes: &[NodeIndex]) -> Self {
+        let positions: HashMap<_, _> = nodes.iter().enumerate().map(|(position, node_index)| (*node_index, position)).collect();
+        let mut roads = HashSet::new();
+        for (from, node_index) in nodes.iter().enumerate() {
+            for edge in network.get_node(*node_index).unwrap().neighbours.iter() {
+                if let Some(to) = positions.get(&edge.destination) {
+                    if from != *to {
+                        roads.insert((from.min(*to), from.max(*to)));
+                    }
+                }
+            }
+        }
+        let mut roads: Vec<_> = roads.into_iter().collect();
+        roads.sort();
+
+        let mut degrees = vec![0; nodes.len()];
+        for (a, b) in roads.iter() {
+            degrees[*a] += 1;
+            degrees[*b] += 1;
+        }
+        let mut first_arcs = vec![0; nodes.len() + 1];
+        for (position, degree) in degrees.iter().enumerate() {
+            first_arcs[position + 1] = first_arcs[position] + degree;
+        }
+
+        let mut next_arcs = first_arcs.clone();
+        let mut arc_targets = vec![0; first_arcs[nodes.len()]];
+        let mut reverse_arcs = vec![0; first_arcs[nodes.len()]];
+        for (a, b) in roads {
+            let (forward, backward) = (next_arcs[a], next_arcs[b]);
+            next_arcs[a] += 1;
+            next_arcs[b] += 1;
+            arc_targets[forward] = b;
+            arc_targets[backward] = a;
+            reverse_arcs[forward] = backward;
+            reverse_arcs[backward] = forward;
+        }
+
+        FlowGraph {first_arcs, arc_targets, reverse_arcs}
+    }
+
+
+    fn arcs(&self, node: usize) -> Range<usize> {
+        self.first_arcs[node]..self.first_arcs[node + 1]
+    }
+
+
+    /// The connected components of the graph, largest first.
+    fn components(&self) -> Vec<Vec<usize>> {
+        let num_nodes = self.first_arcs.len() - 1;
+        let mut visited = vec![false; num_nodes];
+        let mut components = Vec::new();
+        for start in 0..num_nodes {
+            if visited[start] {
+                continue;
+            }
+            visited[start] = true;
+            let mut component = vec![start];
+            let mut next = 0;
+            while next < component.len() {
+                let node = component[next];
+                next += 1;
+                for arc in self.arcs(node) {
+                    let target = self.arc_targets[arc];
+                    if !visited[target] {
+                        visited[target] = true;
+                        component.push(target);
+                    }
+                }
+            }
+            components.push(component);
+        }
+        components.sort_by_key(|component| Reverse(component.len()));
+        components
+    }
+
+
+    /// The size of a minimum cut between the sources and sinks, with whether each node
+    /// is on the source side, found with the Edmonds-Karp algorithm.
+    ///
+    /// Of the two minimum cuts closest to the sources and closest to the sinks, the one
+    /// splitting the nodes more evenly is returned.
+    fn min_cut(&self, sources: &[usize], sinks: &[usize]) -> (usize, Vec<bool>) {
+        let num_nodes = self.first_arcs.len() - 1;
+        let mut capacities = vec![1; self.arc_targets.len()];
+        let mut is_sink = vec![false; num_nodes];
+        sinks.iter().for_each(|sink| is_sink[*sink] = true);
+
+        let mut flow = 0;
+        while let Some(path) = self.augmenting_path(&capacities, sources, &is_sink) {
+            for arc in path {
+                capacities[arc] -= 1;
+                capacities[self.reverse_arcs[arc]] += 1;
+            }
+            flow += 1;
+        }
+
+        // The nodes the sources can still reach, and the nodes which can still reach the sinks.
+        let source_side = self.residual_reachable(&capacities, sources, false);
+        let sink_side = self.residual_reachable(&capacities, sinks, true);
+        let near_sinks: Vec<_> = sink_side.iter().map(|reaches_sink| !reaches_sink).collect();
+
+        let imbalance = |side: &[bool]| {
+            let size = side.iter().filter(|in_side| **in_side).count();
+            size.abs_diff(num_nodes - size)
+        };
+        if imbalance(&near_sinks) < imbalance(&source_side) {
+            (flow, near_sinks)
+        } else {
+            (flow, source_side)
+        }
+    }
+
+
+    /// The arcs of a shortest path with spare capacity from any source to any sink.
+    fn augmenting_path(&self, capacities: &[u32], sources: &[usize], is_sink: &[bool]) -> Option<Vec<usize>> {
+        let mut parent_arcs: Vec<Option<usize>> = vec![None; is_sink.len()];
+        let mut visited = vec![false; is_sink.len()];
+        let mut queue: VecDeque<_> = sources.iter().cloned().collect();
+        sources.iter().for_each(|source| visited[*source] = true);
+
+        while let Some(node) = queue.pop_front() {
+            if is_sink[node] {
+                let mut path = Vec::new();
+                let mut node = node;
+                while let Some(arc) = parent_arcs[node] {
+                    path.push(arc);
+                    node = self.arc_targets[self.reverse_arcs[arc]];
+                }
+                return Some(path);
+            }
+            for arc in self.arcs(node) {
+                let target = self.arc_targets[arc];
+                if capacities[arc] > 0 && !visited[target] {
+                    visited[target] = true;
+                    parent_arcs[target] = Some(arc);
+                    queue.push_back(target);
+                }
+            }
+        }
+        None
+    }
+
+
+    /// The nodes reachable from the starts along arcs with spare capacity, or which can
+    /// reach them if `backward` is set.
+    fn residual_reachable(&self, capacities: &[u32], starts: &[usize], backward: bool) -> Vec<bool> {
+        let mut visited = vec![false; self.first_arcs.len() - 1];
+        let mut queue: VecDeque<_> = starts.iter().cloned().collect();
+        starts.iter().for_each(|start| visited[*start] = true);
+
+        while let Some(node) = queue.pop_front() {
+            for arc in self.arcs(node) {
+                let target = self.arc_targets[arc];
+                // Going backwards the arc into this node is the reverse of the one out of it.
+                let capacity = if backward { capacities[self.reverse_arcs[arc]] } else { capacities[arc] };
+                if capacity > 0 && !visited[target] {
+                    visited[target] = true;
+                    queue.push_back(target);
+                }
+            }
+        }
+        visited
+    }
+}
+
+
+/// How good a division of the network into cells is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PartitionReport {
+    pub num_cells: usize,
+    /// The number of roads between nodes in different cells, counting a road with edges
+    /// in both directions once, as `Bisection::cut_size` does.
+    pub cut_roads: usize,
+    pub min_cell_size: usize,
+    pub max_cell_size: usize,
+    /// The size of the largest cell relative to the average, 1 when perfectly balanced.
+    pub imbalance: f64,
+    /// Nodes which aren't in any cell.
+    pub unassigned_nodes: usize,
+}
+
+
+impl PartitionReport {
+
+    /// Report on the cells given for each node, such as by `recursive_bisection`, a level
+    /// of a multilevel partition or the regions of arc flags.
+    pub fn new<C>(network: &RoadNetwork, cell_of: C) -> Self
+        where C: Fn(NodeIndex) -> Option<usize>
+    {
+        let mut cell_sizes: HashMap<usize, usize> = HashMap::new();
+        let mut cut_roads = HashSet::new();
+        let mut unassigned_nodes = 0;
+        for (node_index, node) in network.nodes_iter() {
+            let cell = match cell_of(*node_index) {
+                Some(cell) => cell,
+                None => {
+                    unassigned_nodes += 1;
+                    continue;
+                },
+            };
+            *cell_sizes.entry(cell).or_insert(0) += 1;
+            cut_roads.extend(node.neighbours.iter()
+                .filter(|edge| cell_of(edge.destination).is_some_and(|other| other != cell))
+                .map(|edge| (edge.destination.min(*node_index), edge.destination.max(*node_index))));
+        }
+
+        let num_cells = cell_sizes.len();
+        let min_cell_size = cell_sizes.values().cloned().min().unwrap_or(0);
+        let max_cell_size = cell_sizes.values().cloned().max().unwrap_or(0);
+        let num_assigned = network.num_nodes() - unassigned_nodes;
+        let imbalance = if num_assigned == 0 {
+            1.
+        } else {
+            max_cell_size as f64 * num_cells as f64 / num_assigned as f64
+        };
+        let cut_roads = cut_roads.len();
+        PartitionReport {num_cells, cut_roads, min_cell_size, max_cell_size, imbalance, unassigned_nodes}
+    }
+}
+
+
+impl fmt::Display for PartitionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} cells of {} to {} nodes, imbalance {:.2}, {} cut roads",
+               self.num_cells, self.min_cell_size, self.max_cell_size, self.imbalance, self.cut_roads)?;
+        if self.unassigned_nodes > 0 {
+            write!(f, ", {} nodes in no cell", self.unassigned_nodes)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::partition::*;
+    use crate::geo_utils::Location;
+    use crate::osm_reader::read_osm_extract;
+    use crate::road_network::Node;
+    use crate::road_network_builder::build_road_network_from_osm;
+    use crate::test_utils::build_grid_network;
+
+    use std::path::Path;
+
+
+    fn all_nodes(network: &RoadNetwork) -> Vec<NodeIndex> {
+        network.nodes_iter().map(|(node_index, _)| *node_index).collect()
+    }
+
+
+    #[test]
+    fn test_bisect_grid() {
+        // Ten columns and six rows, so cutting between two columns crosses the fewest roads.
+        let network = build_grid_network(6, 10);
+        let bisection = inertial_flow_bisection(&network, &all_nodes(&network), &Default::default());
+
+        assert_eq!(6, bisection.cut_size);
+        assert_eq!(60, bisection.first.len() + bisection.second.len());
+        assert!(bisection.first.len() >= 15 && bisection.second.len() >= 15);
+
+        let sides: HashMap<_, _> = bisection.first.iter().map(|node_index| (*node_index, 0))
+            .chain(bisection.second.iter().map(|node_index| (*node_index, 1)))
+            .collect();
+        let report = PartitionReport::new(&network, |node_index| sides.get(&node_index).cloned());
+        assert_eq!(bisection.cut_size, report.cut_roads);
+        assert_eq!(2, report.num_cells);
+    }
+
+
+    #[test]
+    fn test_bisect_subset() {
+        let network = build_grid_network(4, 4);
+        // The top two rows, where roads to the rest of the grid don't count.
+        let nodes: Vec<_> = (8..16).collect();
+        let bisection = inertial_flow_bisection(&network, &nodes, &Default::default());
+        assert_eq!(2, bisection.cut_size);
+        assert_eq!(4, bisection.first.len());
+        assert_eq!(4, bisection.second.len());
+
+        let single = inertial_flow_bisection(&network, &[3], &Default::default());
+        assert_eq!(vec![3], single.first);
+        assert!(single.second.is_empty());
+    }
+
+
+    #[test]
+    fn test_disconnected_nodes() {
+        let mut network = build_grid_network(4, 4);
+        network.add_node(Node::new(99, Location::new(49.01, 7.0))).unwrap();
+
+        // Keeping the grid whole would leave 99 on its own, so the grid is cut instead and
+        // 99 joins the smaller part.
+        let bisection = inertial_flow_bisection(&network, &all_nodes(&network), &Default::default());
+        assert_eq!(4, bisection.cut_size);
+        assert_eq!(17, bisection.first.len() + bisection.second.len());
+        assert_eq!(1, bisection.first.len().abs_diff(bisection.second.len()), "{:?}", bisection);
+        assert!(bisection.first.contains(&99) || bisection.second.contains(&99));
+    }
+
+
+    #[test]
+    fn test_recursive_bisection() {
+        let network = build_grid_network(8, 8);
+        let cells = recursive_bisection(&network, 2, &Default::default()).unwrap();
+        let report = PartitionReport::new(&network, |node_index| cells.get(&node_index).cloned());
+
+        assert_eq!(4, report.num_cells);
+        assert_eq!(0, report.unassigned_nodes);
+        assert!(report.min_cell_size >= 4);
+        assert!(report.imbalance <= 2.5);
+        // No worse than cutting the grid into quarters along straight lines.
+        assert!(report.cut_roads <= 16, "{}", report);
+
+        assert_eq!(64, recursive_bisection(&network, 6, &Default::default()).unwrap().len());
+        assert!(recursive_bisection(&network, 7, &Default::default()).is_err());
+        assert!(recursive_bisection(&network, 64, &Default::default()).is_err());
+    }
+
+
+    #[test]
+    fn test_fixture() {
+        let fixture_path = Path::new("tests").join("fixtures").join("test.osm");
+        let osm = read_osm_extract(fixture_path.to_str().unwrap()).unwrap();
+        let network = build_road_network_from_osm(osm).unwrap();
+
+        // The way isn't a highway, so there are no roads to cut.
+        let bisection = inertial_flow_bisection(&network, &all_nodes(&network), &Default::default());
+        assert_eq!(0, bisection.cut_size);
+        assert_eq!(network.num_nodes(), bisection.first.len() + bisection.second.len());
+        assert!(bisection.first.len() >= bisection.second.len());
+    }
+
+
+    #[test]
+    fn test_report() {
+        let network = build_grid_network(2, 2);
+        let report = PartitionReport::new(&network, |node_index| if node_index < 3 { Some((node_index % 2) as usize) } else { None });
+
+        assert_eq!(2, report.num_cells);
+        assert_eq!(1, report.unassigned_nodes);
+        assert_eq!((1, 2), (report.min_cell_size, report.max_cell_size));
+        assert!((report.imbalance - 4. / 3.).abs() < 1e-9);
+        // Only the road between 0 and 1 joins two cells, as 3 is in no cell.
+        assert_eq!(1, report.cut_roads);
+        assert_eq!("2 cells of 1 to 2 nodes, imbalance 1.33, 1 cut roads, 1 nodes in no cell", report.to_string());
+    }
+}
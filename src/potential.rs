@@ -0,0 +1,105 @@
+use crate::geo_utils::{earth_distance, Location};
+use crate::osm_reader::HighwayType;
+use crate::road_network::{Cost, Node};
+
+
+/// A lower bound on the cost from any node to the target of a query, used to direct
+/// the search towards the target.
+///
+/// For the search to find shortest paths the potential must be consistent, that is
+/// `potential(u) <= cost(u, v) + potential(v)` for every edge from `u` to `v`. Debug
+/// builds check this on every edge the search relaxes.
+pub trait Potential {
+
+    /// Prepare for a query towards the target. Called once before each search starts.
+    fn init(&mut self, _target: &Node) {}
+
+    /// A lower bound on the cost from the node to the target.
+    fn potential(&self, node: &Node) -> Cost;
+}
+
+
+/// No lower bound, turning A* back into Dijkstra's algorithm.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ZeroPotential;
+
+
+impl Potential for ZeroPotential {
+    fn potential(&self, _node: &Node) -> Cost {
+        0
+    }
+}
+
+
+/// The great circle distance to the target travelled at a maximum speed.
+#[derive(Clone, Copy, Debug)]
+pub struct GeometricPotential {
+    max_speed_ms: f64,
+    target: Option<Location>,
+}
+
+
+impl GeometricPotential {
+    /// The speed must be at least as fast as any edge of the network is travelled at.
+    pub fn new(max_speed_ms: f64) -> Self {
+        GeometricPotential {max_speed_ms, target: None}
+    }
+}
+
+
+impl Default for GeometricPotential {
+    /// Uses the fastest speed of any highway type.
+    fn default() -> Self {
+        GeometricPotential::new(HighwayType::max_speed_ms())
+    }
+}
+
+
+impl Potential for GeometricPotential {
+    fn init(&mut self, target: &Node) {
+        self.target = Some(target.location);
+    }
+
+    fn potential(&self, node: &Node) -> Cost {
+        match self.target {
+            Some(ref target) => (earth_distance(&node.location, target) / self.max_speed_ms) as Cost,
+            None => 0,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::potential::*;
+    use crate::shortest_path::dijkstra_shortest_path;
+    use crate::test_utils::build_grid_network;
+
+
+    #[test]
+    fn test_zero_potential() {
+        let network = build_grid_network(2, 2);
+        let node = network.get_node(0).unwrap();
+
+        let mut potential = ZeroPotential;
+        potential.init(network.get_node(3).unwrap());
+        assert_eq!(0, potential.potential(node));
+    }
+
+
+    #[test]
+    fn test_geometric_potential_is_a_lower_bound() {
+        let network = build_grid_network(4, 4);
+        let end = network.get_node(15).unwrap();
+
+        let mut potential = GeometricPotential::default();
+        assert_eq!(0, potential.potential(network.get_node(0).unwrap()));
+
+        potential.init(end);
+        assert!(potential.potential(network.get_node(0).unwrap()) > 0);
+        for (_, node) in network.nodes_iter() {
+            let cost = dijkstra_shortest_path(&network, node, end).unwrap().cost;
+            assert!(potential.potential(node) <= cost);
+        }
+    }
+}
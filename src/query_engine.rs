@@ -0,0 +1,207 @@
+use crate::road_network::{Cost, RoadNetwork, Node, NodeIndex};
+use crate::shortest_path::{SearchStatistics, ShortestPath};
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::time::Instant;
+
+
+/// A workspace for answering many shortest path queries on one network.
+///
+/// The network is copied into dense arrays when the engine is created, and the costs
+/// and parents of each query are kept in arrays which are reused by the next query.
+/// Rather than clearing them, each query has a timestamp and entries written by an
+/// earlier query are treated as unset.
+pub struct QueryEngine<'a> {
+    network: &'a RoadNetwork,
+    node_ids: Vec<NodeIndex>,
+    dense_indices: HashMap<NodeIndex, usize>,
+    /// The edges of node `i` are `first_edges[i]..first_edges[i + 1]`.
+    first_edges: Vec<usize>,
+    edge_targets: Vec<usize>,
+    edge_costs: Vec<Cost>,
+
+    costs: Vec<Cost>,
+    parents: Vec<usize>,
+    /// The query which last wrote a node's cost, and which last settled it.
+    reached: Vec<u32>,
+    settled: Vec<u32>,
+    timestamp: u32,
+    heap: BinaryHeap<Reverse<(Cost, usize)>>,
+}
+
+
+impl<'a> QueryEngine<'a> {
+
+    pub fn new(network: &'a RoadNetwork) -> Self {
+        let mut node_ids: Vec<_> = network.nodes_iter().map(|(node_index, _)| *node_index).collect();
+        node_ids.sort();
+        let dense_indices: HashMap<_, _> = node_ids.iter()
+            .enumerate()
+            .map(|(dense_index, node_index)| (*node_index, dense_index))
+            .collect();
+
+        let mut first_edges = Vec::with_capacity(node_ids.len() + 1);
+        let mut edge_targets = Vec::new();
+        let mut edge_costs = Vec::new();
+        for node_index in node_ids.iter() {
+            first_edges.push(edge_targets.len());
+            for edge in network.get_node(*node_index).unwrap().neighbours.iter() {
+                if let Some(target) = dense_indices.get(&edge.destination) {
+                    edge_targets.push(*target);
+                    edge_costs.push(edge.cost);
+                }
+            }
+        }
+        first_edges.push(edge_targets.len());
+
+        let num_nodes = node_ids.len();
+        QueryEngine {
+            network,
+            node_ids,
+            dense_indices,
+            first_edges,
+            edge_targets,
+            edge_costs,
+            costs: vec![0; num_nodes],
+            parents: vec![0; num_nodes],
+            reached: vec![0; num_nodes],
+            settled: vec![0; num_nodes],
+            timestamp: 0,
+            heap: BinaryHeap::new(),
+        }
+    }
+
+
+    pub fn network(&self) -> &'a RoadNetwork {
+        self.network
+    }
+
+
+    /// Dijkstra's algorithm, finding the same paths as `dijkstra_shortest_path`.
+    pub fn shortest_path(&mut self, start_node: &Node, end_node: &Node) -> Option<ShortestPath> {
+        let start_time = Instant::now();
+        let mut statistics = SearchStatistics::default();
+
+        let start = *self.dense_indices.get(&start_node.id)?;
+        let end = *self.dense_indices.get(&end_node.id)?;
+        self.next_timestamp();
+
+        self.heap.clear();
+        self.reach(start, 0, start);
+        self.heap.push(Reverse((0, start)));
+        statistics.record_push(self.heap.len());
+
+        while let Some(Reverse((cost, node))) = self.heap.pop() {
+            if self.settled[node] == self.timestamp {
+                continue;
+            }
+            self.settled[node] = self.timestamp;
+            statistics.settled_nodes += 1;
+
+            if node == end {
+                statistics.duration = start_time.elapsed();
+                return Some(ShortestPath {cost, path: self.trace_path(start, end), statistics: Some(statistics)});
+            }
+
+            for edge in self.first_edges[node]..self.first_edges[node + 1] {
+                let target = self.edge_targets[edge];
+                let target_cost = cost + self.edge_costs[edge];
+                statistics.relaxed_edges += 1;
+                if self.reached[target] != self.timestamp || target_cost < self.costs[target] {
+                    self.reach(target, target_cost, node);
+                    self.heap.push(Reverse((target_cost, target)));
+                    statistics.record_push(self.heap.len());
+                }
+            }
+        }
+
+        None
+    }
+
+
+    /// Start a new query, clearing the arrays for real only when the timestamp wraps around.
+    fn next_timestamp(&mut self) {
+        if self.timestamp == u32::MAX {
+            self.reached.iter_mut().for_each(|timestamp| *timestamp = 0);
+            self.settled.iter_mut().for_each(|timestamp| *timestamp = 0);
+            self.timestamp = 0;
+        }
+        self.timestamp += 1;
+    }
+
+
+    fn reach(&mut self, node: usize, cost: Cost, parent: usize) {
+        self.costs[node] = cost;
+        self.parents[node] = parent;
+        self.reached[node] = self.timestamp;
+    }
+
+
+    fn trace_path(&self, start: usize, end: usize) -> Vec<NodeIndex> {
+        let mut path = vec![self.node_ids[end]];
+        let mut node = end;
+        while node != start {
+            node = self.parents[node];
+            path.push(self.node_ids[node]);
+        }
+        path.reverse();
+        path
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::query_engine::*;
+    use crate::geo_utils::Location;
+    use crate::shortest_path::dijkstra_shortest_path;
+    use crate::test_utils::build_grid_network;
+
+
+    #[test]
+    fn test_matches_dijkstra() {
+        let network = build_grid_network(5, 5);
+        let mut engine = QueryEngine::new(&network);
+
+        for (_, start) in network.nodes_iter() {
+            for (_, end) in network.nodes_iter() {
+                let expected = dijkstra_shortest_path(&network, start, end).unwrap();
+                let result = engine.shortest_path(start, end).unwrap();
+                assert_eq!(expected.cost(), result.cost());
+                assert_eq!(Some(&start.id), result.path().first());
+                assert_eq!(Some(&end.id), result.path().last());
+            }
+        }
+    }
+
+
+    #[test]
+    fn test_unreachable_and_unknown_nodes() {
+        let mut network = build_grid_network(2, 2);
+        network.add_node(Node::new(99, Location::new(0., 0.))).unwrap();
+        let outside = Node::new(100, Location::new(0., 0.));
+        let mut engine = QueryEngine::new(&network);
+
+        let start = network.get_node(0).unwrap();
+        assert!(engine.shortest_path(start, network.get_node(99).unwrap()).is_none());
+        assert!(engine.shortest_path(start, &outside).is_none());
+        assert_eq!(0, engine.shortest_path(start, start).unwrap().cost());
+    }
+
+
+    #[test]
+    fn test_timestamp_wrap_around() {
+        let network = build_grid_network(3, 3);
+        let mut engine = QueryEngine::new(&network);
+        let start = network.get_node(0).unwrap();
+        let end = network.get_node(8).unwrap();
+        let expected = engine.shortest_path(start, end).unwrap();
+
+        engine.timestamp = u32::MAX - 1;
+        for _ in 0..3 {
+            assert_eq!(expected, engine.shortest_path(start, end).unwrap());
+        }
+        assert_eq!(2, engine.timestamp);
+    }
+}
@@ -2,6 +2,8 @@ extern crate geo;
 
 use crate::connected_components::strongly_connected_components;
 use crate::geo_utils::Location;
+use crate::time_dependent::TravelTimeFunction;
+use crate::turn_restrictions::TurnRestriction;
 
 use std::collections::HashMap;
 use std::collections::hash_map;
@@ -34,25 +36,38 @@ impl Node {
 
 pub struct Edge {
     pub destination: NodeIndex,
-    pub cost: Cost
+    pub cost: Cost,
+    /// Length of the edge in metres.
+    pub distance: u64,
+    /// How the travel time varies over the day, used instead of the cost by time dependent searches.
+    pub travel_time_function: Option<TravelTimeFunction>,
 }
 
 
 pub struct ReverseEdge {
     pub origin: NodeIndex,
-    pub cost: Cost
+    pub cost: Cost,
+    /// Length of the edge in metres.
+    pub distance: u64,
 }
 
 
 pub struct RoadNetwork {
-    nodes: HashMap<NodeIndex, Node>
+    nodes: HashMap<NodeIndex, Node>,
+    turn_restrictions: Vec<TurnRestriction>,
+}
+
+impl Default for RoadNetwork {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RoadNetwork {
 
     /// Construct an empty network.
     pub fn new() -> Self {
-        RoadNetwork {nodes: HashMap::new()}
+        RoadNetwork {nodes: HashMap::new(), turn_restrictions: Vec::new()}
     }
 
 
@@ -70,15 +85,62 @@ impl RoadNetwork {
     ///
     /// TODO (Simon): Handle edges between nodes that don't exist already
     pub fn add_edge(&mut self, from_node_index: NodeIndex, to_node_index: NodeIndex, cost: Cost) {
+        self.add_edge_with_distance(from_node_index, to_node_index, cost, 0);
+    }
+
+
+    /// Adds an edge which is `distance` metres long.
+    pub fn add_edge_with_distance(&mut self,
+                                  from_node_index: NodeIndex,
+                                  to_node_index: NodeIndex,
+                                  cost: Cost,
+                                  distance: u64) {
         {
             let from_node = self.get_node_mut(from_node_index).unwrap();
-            from_node.neighbours.push(Edge { destination: to_node_index, cost });
+            from_node.neighbours.push(Edge {
+                destination: to_node_index,
+                cost,
+                distance,
+                travel_time_function: None,
+            });
         }
 
         {
             let to_node = self.get_node_mut(to_node_index).unwrap();
-            to_node.reverse_neighbours.push(ReverseEdge { origin: from_node_index, cost });
+            to_node.reverse_neighbours.push(ReverseEdge { origin: from_node_index, cost, distance });
+        }
+    }
+
+
+    /// Sets the travel time function of every edge from one node to another.
+    pub fn set_travel_time_function(&mut self,
+                                    from_node_index: NodeIndex,
+                                    to_node_index: NodeIndex,
+                                    function: TravelTimeFunction) -> Result<(), Box<dyn Error>> {
+        let from_node = self.get_node_mut(from_node_index)
+            .ok_or_else(|| format!("No node {} to set a travel time function from", from_node_index))?;
+
+        let mut found = false;
+        for edge in from_node.neighbours.iter_mut().filter(|edge| edge.destination == to_node_index) {
+            edge.travel_time_function = Some(function.clone());
+            found = true;
+        }
+
+        if !found {
+            return Err(From::from(format!("No edge from {} to {}", from_node_index, to_node_index)));
         }
+        Ok(())
+    }
+
+
+    /// Adds a restriction on the turns routes may take, obeyed by turn aware searches.
+    pub fn add_turn_restriction(&mut self, restriction: TurnRestriction) {
+        self.turn_restrictions.push(restriction);
+    }
+
+
+    pub fn turn_restrictions(&self) -> &[TurnRestriction] {
+        &self.turn_restrictions
     }
 
 
@@ -115,11 +177,22 @@ impl RoadNetwork {
     }
 
 
+    /// The cost of the cheapest edge from one node to another, if they are adjacent.
+    pub fn edge_cost(&self, from_node_index: NodeIndex, to_node_index: NodeIndex) -> Option<Cost> {
+        self.get_node(from_node_index)?
+            .neighbours
+            .iter()
+            .filter(|edge| edge.destination == to_node_index)
+            .map(|edge| edge.cost)
+            .min()
+    }
+
+
     pub fn remove_unused_nodes(&mut self) {
         let nodes_to_remove: Vec<_> = self.nodes
             .iter()
-            .filter(|&(_, ref node)| node.neighbours.is_empty())
-            .map(|(k, _)| k.clone())
+            .filter(|(_, node)| node.neighbours.is_empty())
+            .map(|(k, _)| *k)
             .collect();
 
         for node_id in nodes_to_remove {
@@ -128,14 +201,14 @@ impl RoadNetwork {
     }
 
     pub fn reduce_to_largest_strongly_connected_component(&mut self) {
-        let mut components = strongly_connected_components(&self);
+        let mut components = strongly_connected_components(self);
         components.sort_by_key(|component| component.len());
 
         let node_ids_to_remove: Vec<_> = components
             .into_iter()
             .rev()
             .skip(1)
-            .flat_map(|x| x)
+            .flatten()
             .collect();
 
         for node_id in node_ids_to_remove {
@@ -143,7 +216,7 @@ impl RoadNetwork {
         }
     }
 
-    pub fn nodes_iter(& self) -> NodesIterator {
+    pub fn nodes_iter(&self) -> NodesIterator<'_> {
         self.nodes.iter()
     }
 }
@@ -151,7 +224,7 @@ impl RoadNetwork {
 
 #[cfg(test)]
 mod tests {
-    use road_network::*;
+    use crate::road_network::*;
 
     #[test]
     fn test_construct_network() {
@@ -3,6 +3,7 @@ extern crate itertools;
 use crate::geo_utils::{earth_distance, Location};
 use crate::road_network::{RoadNetwork, Node};
 use crate::osm_reader::{Osm, OsmNode, OsmWay};
+use crate::turn_restrictions::turn_restrictions_from_osm;
 
 use itertools::Itertools;
 
@@ -13,13 +14,16 @@ pub fn build_road_network_from_osm(osm: Osm) -> Result<RoadNetwork, Box<dyn Erro
     let mut network = RoadNetwork::new();
     network = add_nodes_to_network(network, &osm.nodes)?;
     network = add_ways_to_network(network, &osm.ways)?;
+    for restriction in turn_restrictions_from_osm(&osm) {
+        network.add_turn_restriction(restriction);
+    }
     network.reduce_to_largest_strongly_connected_component();
     Ok(network)
 }
 
 
 fn add_nodes_to_network(mut network: RoadNetwork,
-                        nodes: &Vec<OsmNode>) -> Result<RoadNetwork, Box<dyn Error>>  {
+                        nodes: &[OsmNode]) -> Result<RoadNetwork, Box<dyn Error>>  {
     for osm_node in nodes.iter() {
         let location = Location::new(osm_node.lat, osm_node.lon);
         network.add_node(Node::new(osm_node.id, location))?;
@@ -29,9 +33,9 @@ fn add_nodes_to_network(mut network: RoadNetwork,
 
 
 fn add_ways_to_network(mut network: RoadNetwork,
-                       ways: &Vec<OsmWay>) -> Result<RoadNetwork, Box<dyn Error>> {
+                       ways: &[OsmWay]) -> Result<RoadNetwork, Box<dyn Error>> {
     for way in ways.iter() {
-        network = add_way_to_network(network, &way)?;
+        network = add_way_to_network(network, way)?;
     }
     Ok(network)
 }
@@ -46,19 +50,26 @@ fn add_way_to_network(mut network: RoadNetwork, way: &OsmWay) -> Result<RoadNetw
 
     // TODO (Simon): Compute edge costs correctly here.
     for (start_nd, end_nd) in way.nodes.iter().tuple_windows() {
-        let cost = edge_duration_seconds(&network, start_nd.ref_, end_nd.ref_, highway_type.speed_ms());
-        network.add_edge(start_nd.ref_, end_nd.ref_, cost);
-        network.add_edge(end_nd.ref_, start_nd.ref_, cost);
+        let distance = edge_distance_meters(&network, start_nd.ref_, end_nd.ref_);
+        let cost = edge_duration_seconds(distance, highway_type.speed_ms());
+        let distance = distance.round() as u64;
+        network.add_edge_with_distance(start_nd.ref_, end_nd.ref_, cost, distance);
+        network.add_edge_with_distance(end_nd.ref_, start_nd.ref_, cost, distance);
     }
 
     Ok(network)
 }
 
 
-fn edge_duration_seconds(network: &RoadNetwork, start_id: u64, end_id: u64, speed_ms: f64) -> u64 {
+fn edge_distance_meters(network: &RoadNetwork, start_id: u64, end_id: u64) -> f64 {
     let start_node = network.get_node(start_id).unwrap();
     let end_node = network.get_node(end_id).unwrap();
+    earth_distance(&start_node.location, &end_node.location)
+}
+
 
-    let distance_meters = earth_distance(&start_node.location, &end_node.location);
-    (distance_meters / speed_ms) as u64
+fn edge_duration_seconds(distance_meters: f64, speed_ms: f64) -> u64 {
+    // Round up so that an edge is never cheaper than travelling its length at the
+    // maximum speed, this keeps the A* potential in shortest_path admissible.
+    (distance_meters / speed_ms).ceil() as u64
 }
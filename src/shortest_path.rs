@@ -1,7 +1,9 @@
+use crate::potential::{GeometricPotential, Potential, ZeroPotential};
 use crate::road_network::{Cost, RoadNetwork, Node, NodeIndex};
 
 use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::cmp::Ordering;
+use std::time::{Duration, Instant};
 
 
 #[derive(Debug, Default, Eq, PartialEq)]
@@ -28,32 +30,129 @@ impl PartialOrd for HeapEl {
 }
 
 
-#[derive(Debug, Eq, PartialEq)]
+/// Counts of the work a search did to find a path.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SearchStatistics {
+    pub settled_nodes: usize,
+    pub relaxed_edges: usize,
+    pub heap_pushes: usize,
+    pub max_heap_size: usize,
+    pub duration: Duration,
+}
+
+
+impl SearchStatistics {
+
+    pub(crate) fn record_push(&mut self, heap_size: usize) {
+        self.heap_pushes += 1;
+        self.max_heap_size = self.max_heap_size.max(heap_size);
+    }
+
+
+    /// Combine the statistics of two searches run one after another.
+    pub(crate) fn add(&mut self, other: &SearchStatistics) {
+        self.settled_nodes += other.settled_nodes;
+        self.relaxed_edges += other.relaxed_edges;
+        self.heap_pushes += other.heap_pushes;
+        self.max_heap_size = self.max_heap_size.max(other.max_heap_size);
+        self.duration += other.duration;
+    }
+}
+
+
+#[derive(Debug)]
 pub struct ShortestPath {
-    cost: Cost,
-    path: Vec<NodeIndex>,
+    pub(crate) cost: Cost,
+    pub(crate) path: Vec<NodeIndex>,
+    pub(crate) statistics: Option<SearchStatistics>,
+}
+
+
+impl ShortestPath {
+
+    pub fn cost(&self) -> Cost {
+        self.cost
+    }
+
+
+    /// The nodes along the path, starting with the start node and ending with the end node.
+    pub fn path(&self) -> &[NodeIndex] {
+        &self.path
+    }
+
+
+    /// The work done by the search which found this path, summed over every search for
+    /// paths stitched together from several.
+    pub fn statistics(&self) -> Option<&SearchStatistics> {
+        self.statistics.as_ref()
+    }
+}
+
+
+/// Paths are equal when they have the same cost and nodes, however they were found.
+impl PartialEq for ShortestPath {
+    fn eq(&self, other: &ShortestPath) -> bool {
+        self.cost == other.cost && self.path == other.path
+    }
 }
 
 
+impl Eq for ShortestPath {}
+
+
 pub fn dijkstra_shortest_path(network: &RoadNetwork,
                               start_node: &Node,
                               end_node: &Node,
 ) -> Option<ShortestPath> {
-    shortest_path(network, start_node, end_node, |_n1, _n2| 0)
+    shortest_path(network, start_node, end_node, &mut ZeroPotential)
 }
 
 
-fn shortest_path(network: &RoadNetwork,
-                     start_node: &Node,
-                     end_node: &Node,
-                     potential: fn(&Node, &Node) -> Cost,
+/// A* search using the great circle distance at the maximum road speed as the potential.
+///
+/// This is a lower bound on the travel time between any two nodes, so the returned
+/// path has the same cost as the one found by `dijkstra_shortest_path`.
+pub fn astar_shortest_path(network: &RoadNetwork,
+                           start_node: &Node,
+                           end_node: &Node,
 ) -> Option<ShortestPath> {
+    shortest_path(network, start_node, end_node, &mut GeometricPotential::default())
+}
+
+
+/// A* search directed towards the end node by the given potential.
+pub fn shortest_path<P: Potential>(network: &RoadNetwork,
+                                   start_node: &Node,
+                                   end_node: &Node,
+                                   potential: &mut P,
+) -> Option<ShortestPath> {
+    filtered_shortest_path(network, start_node, end_node, potential, |_node, _edge_index| true)
+}
+
+
+/// The potential driven search, only following the edges accepted by the filter.
+///
+/// The filter is given a node and the index of one of its `neighbours`.
+pub(crate) fn filtered_shortest_path<P, E>(network: &RoadNetwork,
+                                           start_node: &Node,
+                                           end_node: &Node,
+                                           potential: &mut P,
+                                           edge_filter: E,
+) -> Option<ShortestPath>
+    where P: Potential,
+          E: Fn(&Node, usize) -> bool
+{
+    potential.init(end_node);
+
+    let start_time = Instant::now();
+    let mut statistics = SearchStatistics::default();
 
     let mut heap = BinaryHeap::new();
     let mut visited = HashSet::new();
     let mut previous_nodes = HashMap::new();
 
     heap.push(HeapEl {cost: 0, potential: 0, node_index: start_node.id, previous_node_index: None});
+    statistics.record_push(heap.len());
 
     while let Some(el) = heap.pop() {
         if visited.contains(&el.node_index) {
@@ -61,25 +160,39 @@ fn shortest_path(network: &RoadNetwork,
         }
         visited.insert(el.node_index);
         previous_nodes.insert(el.node_index, el.previous_node_index);
+        statistics.settled_nodes += 1;
 
         if el.node_index == end_node.id {
+            statistics.duration = start_time.elapsed();
             return Some(ShortestPath {
                 cost: el.cost,
-                path: trace_path(previous_nodes, end_node.id)
+                path: trace_path(&previous_nodes, end_node.id),
+                statistics: Some(statistics),
             });
         }
 
         let node = network.get_node(el.node_index).unwrap();
-        for neighbour in node.neighbours.iter() {
+        for (edge_index, neighbour) in node.neighbours.iter().enumerate() {
+            if !edge_filter(node, edge_index) {
+                continue;
+            }
             let cost = el.cost + neighbour.cost;
             let neighbour_node = network.get_node(neighbour.destination).unwrap();
+            let neighbour_potential = potential.potential(neighbour_node);
+            statistics.relaxed_edges += 1;
+
+            debug_assert!(potential.potential(node) <= neighbour.cost + neighbour_potential,
+                          "Inconsistent potential on the edge from {} to {}: {} > {} + {}",
+                          node.id, neighbour.destination, potential.potential(node),
+                          neighbour.cost, neighbour_potential);
 
             heap.push(HeapEl {
                 cost,
-                potential: cost + potential(neighbour_node, end_node),
+                potential: cost + neighbour_potential,
                 node_index: neighbour.destination,
                 previous_node_index: Some(el.node_index),
             });
+            statistics.record_push(heap.len());
         }
     }
 
@@ -87,7 +200,154 @@ fn shortest_path(network: &RoadNetwork,
 }
 
 
-fn trace_path(previous_nodes: HashMap<NodeIndex, Option<NodeIndex>>,
+/// Which edges a search should follow out of each node.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    /// Follow `neighbours`, giving costs from the source to every node.
+    Forward,
+    /// Follow `reverse_neighbours`, giving costs from every node to the source.
+    Backward,
+}
+
+
+/// The cost of the shortest path between a source and every node it's connected to,
+/// with the parent of each node in a tree of those paths.
+#[derive(Debug)]
+pub struct ShortestPathTree {
+    pub(crate) source: NodeIndex,
+    pub(crate) direction: Direction,
+    pub(crate) costs: HashMap<NodeIndex, Cost>,
+    pub(crate) parents: HashMap<NodeIndex, Option<NodeIndex>>,
+    pub(crate) statistics: SearchStatistics,
+}
+
+
+impl ShortestPathTree {
+
+    pub fn source(&self) -> NodeIndex {
+        self.source
+    }
+
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+
+    /// The cost of every node reached, from the source going forward or to it going backward.
+    pub fn costs(&self) -> &HashMap<NodeIndex, Cost> {
+        &self.costs
+    }
+
+
+    pub fn cost(&self, node_index: NodeIndex) -> Option<Cost> {
+        self.costs.get(&node_index).cloned()
+    }
+
+
+    /// The node before this one on the path from the source, or None for the source
+    /// itself and nodes which weren't reached.
+    pub fn parent(&self, node_index: NodeIndex) -> Option<NodeIndex> {
+        self.parents.get(&node_index).cloned().flatten()
+    }
+
+
+    pub fn num_reached(&self) -> usize {
+        self.costs.len()
+    }
+
+
+    /// The work done to build the tree.
+    pub fn statistics(&self) -> &SearchStatistics {
+        &self.statistics
+    }
+
+
+    /// The path between the source and a node in the direction of travel, so from the
+    /// source in a forward tree and to the source in a backward tree.
+    pub fn path(&self, node_index: NodeIndex) -> Option<Vec<NodeIndex>> {
+        if !self.costs.contains_key(&node_index) {
+            return None;
+        }
+        let mut path = trace_path(&self.parents, node_index);
+        if self.direction == Direction::Backward {
+            path.reverse();
+        }
+        Some(path)
+    }
+}
+
+
+/// Run Dijkstra's algorithm from the source until every reachable node is settled.
+pub fn shortest_path_tree(network: &RoadNetwork,
+                          source: NodeIndex,
+                          direction: Direction) -> ShortestPathTree {
+    bounded_shortest_path_tree(network, source, direction, Cost::MAX)
+}
+
+
+/// Run Dijkstra's algorithm from the source, only settling nodes within the maximum cost.
+pub fn bounded_shortest_path_tree(network: &RoadNetwork,
+                                  source: NodeIndex,
+                                  direction: Direction,
+                                  max_cost: Cost) -> ShortestPathTree {
+    let start_time = Instant::now();
+    let mut statistics = SearchStatistics::default();
+
+    let mut heap = BinaryHeap::new();
+    let mut costs = HashMap::new();
+    let mut parents = HashMap::new();
+
+    if network.get_node(source).is_some() {
+        heap.push(HeapEl {cost: 0, potential: 0, node_index: source, previous_node_index: None});
+        statistics.record_push(heap.len());
+    }
+
+    while let Some(el) = heap.pop() {
+        if el.cost > max_cost {
+            break;
+        }
+        if costs.contains_key(&el.node_index) {
+            continue;
+        }
+        costs.insert(el.node_index, el.cost);
+        parents.insert(el.node_index, el.previous_node_index);
+        statistics.settled_nodes += 1;
+
+        let node = network.get_node(el.node_index).unwrap();
+        for (neighbour, edge_cost) in edges(node, direction) {
+            if costs.contains_key(&neighbour) {
+                continue;
+            }
+            let cost = el.cost + edge_cost;
+            statistics.relaxed_edges += 1;
+            heap.push(HeapEl {
+                cost,
+                potential: cost,
+                node_index: neighbour,
+                previous_node_index: Some(el.node_index),
+            });
+            statistics.record_push(heap.len());
+        }
+    }
+
+    statistics.duration = start_time.elapsed();
+    ShortestPathTree {source, direction, costs, parents, statistics}
+}
+
+
+/// The adjacent nodes and edge costs of a node when searching in the given direction.
+pub(crate) fn edges(node: &Node, direction: Direction) -> Box<dyn Iterator<Item=(NodeIndex, Cost)> + '_> {
+    match direction {
+        Direction::Forward => Box::new(
+            node.neighbours.iter().map(|edge| (edge.destination, edge.cost))),
+        Direction::Backward => Box::new(
+            node.reverse_neighbours.iter().map(|edge| (edge.origin, edge.cost))),
+    }
+}
+
+
+fn trace_path(previous_nodes: &HashMap<NodeIndex, Option<NodeIndex>>,
               end_node: NodeIndex) -> Vec<NodeIndex> {
     let mut path = Vec::new();
     let mut current_node = Some(end_node);
@@ -105,9 +365,10 @@ fn trace_path(previous_nodes: HashMap<NodeIndex, Option<NodeIndex>>,
 
 #[cfg(test)]
 mod tests {
-    use shortest_path::*;
+    use crate::shortest_path::*;
 
-    use geo_utils::Location;
+    use crate::geo_utils::Location;
+    use crate::test_utils::build_grid_network;
 
     fn get_test_network() -> RoadNetwork {
         let mut network = RoadNetwork::new();
@@ -127,6 +388,51 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_shortest_path_tree() {
+        let mut network = build_grid_network(4, 4);
+        // A one way shortcut, so the forward and backward trees differ.
+        network.add_edge(0, 15, 1);
+
+        for direction in [Direction::Forward, Direction::Backward].iter() {
+            let tree = shortest_path_tree(&network, 5, *direction);
+            assert_eq!(5, tree.source());
+            assert_eq!(16, tree.num_reached());
+            assert_eq!(None, tree.parent(5));
+
+            for (node_index, node) in network.nodes_iter() {
+                let (start, end) = match direction {
+                    Direction::Forward => (network.get_node(5).unwrap(), node),
+                    Direction::Backward => (node, network.get_node(5).unwrap()),
+                };
+                let expected = dijkstra_shortest_path(&network, start, end).unwrap();
+                assert_eq!(Some(expected.cost), tree.cost(*node_index));
+
+                let path = tree.path(*node_index).unwrap();
+                assert_eq!(Some(&start.id), path.first());
+                assert_eq!(Some(&end.id), path.last());
+                let path_cost: Cost = path.windows(2).map(|pair| network.edge_cost(pair[0], pair[1]).unwrap()).sum();
+                assert_eq!(expected.cost, path_cost);
+            }
+        }
+
+        assert_eq!(Some(1), shortest_path_tree(&network, 0, Direction::Forward).cost(15));
+        assert!(shortest_path_tree(&network, 15, Direction::Forward).cost(0).unwrap() > 1);
+    }
+
+
+    #[test]
+    fn test_shortest_path_tree_unreachable() {
+        let network = get_test_network();
+        let tree = shortest_path_tree(&network, 1, Direction::Forward);
+        assert_eq!(4, tree.num_reached());
+        assert_eq!(None, tree.cost(5));
+        assert_eq!(None, tree.path(5));
+
+        assert_eq!(0, shortest_path_tree(&network, 99, Direction::Forward).num_reached());
+    }
+
+
     #[test]
     fn test_heap() {
         let mut heap = BinaryHeap::new();
@@ -150,7 +456,7 @@ mod tests {
         let network = get_test_network();
         let node = network.get_node(1).unwrap();
 
-        let result = dijkstra_shortest_path(&network, &node, &node).unwrap();
+        let result = dijkstra_shortest_path(&network, node, node).unwrap();
         assert_eq!(0, result.cost);
         assert_eq!(vec![1], result.path);
     }
@@ -175,4 +481,75 @@ mod tests {
         assert_eq!(35, result.cost);
         assert_eq!(vec![1, 2, 3, 4], result.path);
     }
+
+
+    #[test]
+    fn test_astar_finds_same_cost_as_dijkstra() {
+        let network = build_grid_network(4, 4);
+
+        for start_index in 0..16 {
+            for end_index in 0..16 {
+                let start = network.get_node(start_index).unwrap();
+                let end = network.get_node(end_index).unwrap();
+
+                let dijkstra = dijkstra_shortest_path(&network, start, end).unwrap();
+                let astar = astar_shortest_path(&network, start, end).unwrap();
+                assert_eq!(dijkstra.cost, astar.cost);
+                assert_eq!(Some(&start_index), astar.path.first());
+                assert_eq!(Some(&end_index), astar.path.last());
+            }
+        }
+    }
+
+
+    #[test]
+    fn test_search_statistics() {
+        let network = get_test_network();
+        let start = network.get_node(1).unwrap();
+        let end = network.get_node(4).unwrap();
+
+        let result = dijkstra_shortest_path(&network, start, end).unwrap();
+        assert_eq!(35, result.cost());
+        assert_eq!(&[1, 2, 3, 4], result.path());
+
+        let statistics = result.statistics().unwrap();
+        assert_eq!(4, statistics.settled_nodes);
+        assert_eq!(4, statistics.relaxed_edges);
+        assert_eq!(5, statistics.heap_pushes);
+        assert_eq!(2, statistics.max_heap_size);
+    }
+
+
+    #[test]
+    fn test_astar_settles_fewer_nodes_than_dijkstra() {
+        let network = build_grid_network(8, 8);
+        let start = network.get_node(0).unwrap();
+        let end = network.get_node(27).unwrap();
+
+        let dijkstra = dijkstra_shortest_path(&network, start, end).unwrap();
+        let astar = astar_shortest_path(&network, start, end).unwrap();
+        assert!(astar.statistics().unwrap().settled_nodes < dijkstra.statistics().unwrap().settled_nodes);
+    }
+
+
+    /// Claims the start node is much further from the end than it is.
+    struct InconsistentPotential;
+
+    impl Potential for InconsistentPotential {
+        fn potential(&self, node: &Node) -> Cost {
+            if node.id == 1 { 1000 } else { 0 }
+        }
+    }
+
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Inconsistent potential on the edge from 1 to 2")]
+    fn test_inconsistent_potential_is_detected() {
+        let network = get_test_network();
+        let start = network.get_node(1).unwrap();
+        let end = network.get_node(4).unwrap();
+
+        shortest_path(&network, start, end, &mut InconsistentPotential);
+    }
 }
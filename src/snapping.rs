@@ -0,0 +1,362 @@
+use crate::geo_utils::{earth_distance, Location};
+use crate::road_network::{Cost, RoadNetwork, NodeIndex};
+use crate::spatial_index::SpatialIndex;
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+
+const EARTH_RADIUS_METERS: f64 = 6371000.;
+
+
+/// The closest point on an edge to a coordinate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EdgeSnap {
+    pub from: NodeIndex,
+    pub to: NodeIndex,
+    /// How far along the edge from `from` to `to` the point is, between 0 and 1.
+    pub fraction: f64,
+    /// The distance in metres from the coordinate to the point.
+    pub distance: f64,
+    pub location: Location,
+}
+
+
+/// Finds the closest edge to a coordinate, using a spatial index over the nodes.
+pub struct EdgeSnapper {
+    index: SpatialIndex,
+    /// Half the length in metres of the longest edge, so every point on an edge is
+    /// within this distance of one of its ends.
+    max_half_edge_length: f64,
+}
+
+
+impl EdgeSnapper {
+
+    pub fn new(network: &RoadNetwork) -> Self {
+        let max_edge_length = network.nodes_iter()
+            .flat_map(|(_, node)| node.neighbours.iter().map(move |edge| (node, edge)))
+            .filter_map(|(node, edge)| network.get_node(edge.destination)
+                .map(|destination| earth_distance(&node.location, &destination.location)))
+            .fold(0., f64::max);
+        EdgeSnapper {index: SpatialIndex::new(network), max_half_edge_length: max_edge_length / 2.}
+    }
+
+
+    /// Project a coordinate onto the closest edge of the network.
+    ///
+    /// Where a road has an edge in each direction either may be returned. Returns None
+    /// if the network has no edges.
+    pub fn snap(&self, network: &RoadNetwork, lat: f64, lng: f64) -> Option<EdgeSnap> {
+        let (_, nearest_distance) = *self.index.k_nearest(lat, lng, 1).first()?;
+        let candidates = self.index.within_radius(lat, lng, nearest_distance + self.max_half_edge_length);
+
+        let mut best: Option<EdgeSnap> = None;
+        for (node_index, _) in candidates {
+            let node = network.get_node(node_index)?;
+            let edges = node.neighbours.iter()
+                .map(|edge| (node_index, edge.destination))
+                .chain(node.reverse_neighbours.iter().map(|edge| (edge.origin, node_index)));
+            for (from, to) in edges {
+                let snap = match project_onto_edge(network, from, to, lat, lng) {
+                    Some(snap) => snap,
+                    None => continue,
+                };
+                let is_better = best.is_none_or(|best| {
+                    (snap.distance, snap.from, snap.to) < (best.distance, best.from, best.to)
+                });
+                if is_better {
+                    best = Some(snap);
+                }
+            }
+        }
+        best
+    }
+
+
+    /// Every edge passing within the radius in metres of a coordinate, closest first.
+    ///
+    /// Both edges of a two way road are included, projected onto separately.
+    pub fn candidates(&self, network: &RoadNetwork, lat: f64, lng: f64, radius: f64) -> Vec<EdgeSnap> {
+        let mut candidates: Vec<_> = self.index.within_radius(lat, lng, radius + self.max_half_edge_length)
+            .into_iter()
+            .filter_map(|(node_index, _)| network.get_node(node_index))
+            .flat_map(|node| {
+                let outgoing = node.neighbours.iter().map(move |edge| (node.id, edge.destination));
+                let incoming = node.reverse_neighbours.iter().map(move |edge| (edge.origin, node.id));
+                outgoing.chain(incoming)
+            })
+            .filter_map(|(from, to)| project_onto_edge(network, from, to, lat, lng))
+            .filter(|snap| snap.distance <= radius)
+            .collect();
+        candidates.sort_by(|a, b| a.distance.total_cmp(&b.distance).then((a.from, a.to).cmp(&(b.from, b.to))));
+        candidates.dedup_by_key(|snap| (snap.from, snap.to));
+        candidates
+    }
+}
+
+
+/// The closest point on the straight line between the ends of an edge to a coordinate.
+pub fn project_onto_edge(network: &RoadNetwork, from: NodeIndex, to: NodeIndex, lat: f64, lng: f64) -> Option<EdgeSnap> {
+    let a = network.get_node(from)?.location;
+    let b = network.get_node(to)?.location;
+
+    // Work on a plane around the coordinate, which is accurate over the length of an edge.
+    let cos_latitude = lat.to_radians().cos();
+    let project = |location: &Location| (
+        (location.lng() - lng).to_radians() * cos_latitude * EARTH_RADIUS_METERS,
+        (location.lat() - lat).to_radians() * EARTH_RADIUS_METERS,
+    );
+    let (ax, ay) = project(&a);
+    let (bx, by) = project(&b);
+    let (dx, dy) = (bx - ax, by - ay);
+
+    let squared_length = dx * dx + dy * dy;
+    let fraction = if squared_length > 0. {
+        ((-ax * dx - ay * dy) / squared_length).clamp(0., 1.)
+    } else {
+        0.
+    };
+
+    let location = Location::new(a.lat() + fraction * (b.lat() - a.lat()),
+                                 a.lng() + fraction * (b.lng() - a.lng()));
+    let distance = earth_distance(&Location::new(lat, lng), &location);
+    Some(EdgeSnap {from, to, fraction, distance, location})
+}
+
+
+/// A path between two points on edges.
+#[derive(Debug, PartialEq)]
+pub struct SnappedPath {
+    cost: Cost,
+    path: Vec<NodeIndex>,
+}
+
+
+impl SnappedPath {
+
+    /// The cost including the parts of the first and last edges travelled.
+    pub fn cost(&self) -> Cost {
+        self.cost
+    }
+
+
+    /// The nodes passed through between the two points, which is empty when both are
+    /// on the same edge.
+    pub fn path(&self) -> &[NodeIndex] {
+        &self.path
+    }
+}
+
+
+/// The share of an edge's cost for travelling the fraction of it.
+fn partial_cost(cost: Cost, fraction: f64) -> Cost {
+    (cost as f64 * fraction).round() as Cost
+}
+
+
+/// The nodes at the ends of a snapped edge, with the cost of travelling between them and
+/// the point, using whichever of the edge and the opposite edge go the right way.
+fn edge_ends(network: &RoadNetwork, snap: &EdgeSnap, leaving: bool) -> Vec<(NodeIndex, Cost)> {
+    let forward = network.edge_cost(snap.from, snap.to);
+    let backward = network.edge_cost(snap.to, snap.from);
+    let (to_fraction, from_fraction) = (1. - snap.fraction, snap.fraction);
+
+    let mut ends = Vec::new();
+    if leaving {
+        if let Some(cost) = forward {
+            ends.push((snap.to, partial_cost(cost, to_fraction)));
+        }
+        if let Some(cost) = backward {
+            ends.push((snap.from, partial_cost(cost, from_fraction)));
+        }
+    } else {
+        if let Some(cost) = forward {
+            ends.push((snap.from, partial_cost(cost, from_fraction)));
+        }
+        if let Some(cost) = backward {
+            ends.push((snap.to, partial_cost(cost, to_fraction)));
+        }
+    }
+    ends
+}
+
+
+/// Dijkstra's algorithm between two points on edges.
+///
+/// The search starts from both ends of the start edge that can be driven to from the
+/// point, with the share of the edge's cost needed to reach them, and finishes at
+/// whichever end of the end edge gives the cheapest total after adding the share of the
+/// edge from there to the point. Points on the same road are also joined directly.
+pub fn snapped_shortest_path(network: &RoadNetwork, start: &EdgeSnap, end: &EdgeSnap) -> Option<SnappedPath> {
+    // The cheapest cost found so far, and the end node it was found through if any.
+    let mut best: Option<(Cost, Option<NodeIndex>)> = None;
+    let same_direction = (start.from, start.to) == (end.from, end.to);
+    if same_direction || (start.to, start.from) == (end.from, end.to) {
+        // Where the end is along the start's edge.
+        let end_fraction = if same_direction { end.fraction } else { 1. - end.fraction };
+
+        let forward_cost = network.edge_cost(start.from, start.to);
+        if let Some(cost) = forward_cost.filter(|_| start.fraction <= end_fraction) {
+            best = Some((partial_cost(cost, end_fraction - start.fraction), None));
+        }
+        let backward_cost = network.edge_cost(start.to, start.from);
+        if let Some(cost) = backward_cost.filter(|_| start.fraction >= end_fraction) {
+            let direct = partial_cost(cost, start.fraction - end_fraction);
+            if best.is_none_or(|(best_cost, _)| direct < best_cost) {
+                best = Some((direct, None));
+            }
+        }
+    }
+
+    let targets: HashMap<_, _> = edge_ends(network, end, false).into_iter().collect();
+    let mut heap = BinaryHeap::new();
+    let mut costs: HashMap<NodeIndex, Cost> = HashMap::new();
+    let mut parents: HashMap<NodeIndex, Option<NodeIndex>> = HashMap::new();
+    for (node_index, cost) in edge_ends(network, start, true) {
+        heap.push(Reverse((cost, node_index, None)));
+    }
+
+    while let Some(Reverse((cost, node_index, parent))) = heap.pop() {
+        if best.is_some_and(|(best_cost, _)| best_cost <= cost) {
+            break;
+        }
+        if costs.contains_key(&node_index) {
+            continue;
+        }
+        costs.insert(node_index, cost);
+        parents.insert(node_index, parent);
+
+        if let Some(target_cost) = targets.get(&node_index) {
+            if best.is_none_or(|(best_cost, _)| cost + target_cost < best_cost) {
+                best = Some((cost + target_cost, Some(node_index)));
+            }
+        }
+
+        let node = network.get_node(node_index).unwrap();
+        for edge in node.neighbours.iter() {
+            if !costs.contains_key(&edge.destination) {
+                heap.push(Reverse((cost + edge.cost, edge.destination, Some(node_index))));
+            }
+        }
+    }
+
+    let (cost, last_node) = best?;
+    let mut path = Vec::new();
+    let mut current = last_node;
+    while let Some(node_index) = current {
+        path.push(node_index);
+        current = parents[&node_index];
+    }
+    path.reverse();
+    Some(SnappedPath {cost, path})
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::snapping::*;
+    use crate::road_network::Node;
+    use crate::shortest_path::dijkstra_shortest_path;
+    use crate::test_utils::build_grid_network;
+
+
+    /// A long rural road from 1 through 2 to 3, with each edge about 7km long.
+    fn get_test_network() -> RoadNetwork {
+        let mut network = RoadNetwork::new();
+        for i in 1..4 {
+            network.add_node(Node::new(i, Location::new(49.0, 7.0 + 0.1 * (i - 1) as f64))).unwrap();
+        }
+        network.add_edge(1, 2, 100);
+        network.add_edge(2, 1, 100);
+        network.add_edge(2, 3, 100);
+        network.add_edge(3, 2, 100);
+        network
+    }
+
+
+    #[test]
+    fn test_snap_to_edge() {
+        let network = get_test_network();
+        let snapper = EdgeSnapper::new(&network);
+
+        let snap = snapper.snap(&network, 49.001, 7.025).unwrap();
+        assert_eq!((1, 2), (snap.from, snap.to));
+        assert!((snap.fraction - 0.25).abs() < 1e-3);
+        assert!((snap.distance - 111.).abs() < 1.);
+        assert!((snap.location.lng() - 7.025).abs() < 1e-4);
+
+        // Beyond the end of the road the closest point is the end node itself.
+        let snap = snapper.snap(&network, 49.0, 7.3).unwrap();
+        assert_eq!(3, if snap.fraction == 0. { snap.from } else { snap.to });
+
+        assert!(EdgeSnapper::new(&RoadNetwork::new()).snap(&network, 49.0, 7.0).is_none());
+    }
+
+
+    #[test]
+    fn test_candidates() {
+        let network = build_grid_network(3, 3);
+        let snapper = EdgeSnapper::new(&network);
+
+        // Next to the road between 0 and 1, and too far from any other.
+        let candidates = snapper.candidates(&network, 49.0002, 7.0007, 40.);
+        let edges: Vec<_> = candidates.iter().map(|snap| (snap.from, snap.to)).collect();
+        assert_eq!(vec![(0, 1), (1, 0)], edges);
+        assert!(candidates.iter().all(|snap| (snap.distance - 22.).abs() < 1.));
+
+        assert_eq!(4, snapper.candidates(&network, 49.0, 7.0, 1.).len());
+        assert!(snapper.candidates(&network, 48.0, 7.0, 40.).is_empty());
+    }
+
+
+    #[test]
+    fn test_snapped_shortest_path() {
+        let network = get_test_network();
+        let snapper = EdgeSnapper::new(&network);
+        let start = snapper.snap(&network, 49.0, 7.025).unwrap();
+        let middle = snapper.snap(&network, 49.0, 7.075).unwrap();
+        let end = snapper.snap(&network, 49.0, 7.15).unwrap();
+
+        let result = snapped_shortest_path(&network, &start, &end).unwrap();
+        assert_eq!(125, result.cost());
+        assert_eq!(&[2], result.path());
+
+        let result = snapped_shortest_path(&network, &end, &start).unwrap();
+        assert_eq!(125, result.cost());
+
+        // Along the same road in either direction.
+        let result = snapped_shortest_path(&network, &start, &middle).unwrap();
+        assert_eq!(50, result.cost());
+        assert!(result.path().is_empty());
+        assert_eq!(50, snapped_shortest_path(&network, &middle, &start).unwrap().cost());
+    }
+
+
+    #[test]
+    fn test_one_way_edge() {
+        let mut network = RoadNetwork::new();
+        network.add_node(Node::new(1, Location::new(49.0, 7.0))).unwrap();
+        network.add_node(Node::new(2, Location::new(49.0, 7.1))).unwrap();
+        network.add_edge(1, 2, 100);
+
+        let start = project_onto_edge(&network, 1, 2, 49.0, 7.075).unwrap();
+        let end = project_onto_edge(&network, 1, 2, 49.0, 7.025).unwrap();
+        assert!(snapped_shortest_path(&network, &start, &end).is_none());
+        assert_eq!(50, snapped_shortest_path(&network, &end, &start).unwrap().cost());
+    }
+
+
+    #[test]
+    fn test_snapping_at_nodes_matches_dijkstra() {
+        let network = build_grid_network(4, 4);
+        let snapper = EdgeSnapper::new(&network);
+        let start = network.get_node(0).unwrap();
+        let end = network.get_node(15).unwrap();
+
+        let start_snap = snapper.snap(&network, start.location.lat(), start.location.lng()).unwrap();
+        let end_snap = snapper.snap(&network, end.location.lat(), end.location.lng()).unwrap();
+        let result = snapped_shortest_path(&network, &start_snap, &end_snap).unwrap();
+        assert_eq!(dijkstra_shortest_path(&network, start, end).unwrap().cost(), result.cost());
+    }
+}
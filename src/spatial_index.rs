@@ -0,0 +1,351 @@
+use crate::connected_components::strongly_connected_components;
+use crate::geo_utils::{earth_distance, Location};
+use crate::road_network::{RoadNetwork, Node, NodeIndex};
+
+use std::collections::HashSet;
+use std::f64::consts::PI;
+
+
+const EARTH_RADIUS_METERS: f64 = 6371000.;
+
+
+/// A node's location as a point in space on a sphere the size of the earth, in metres.
+#[derive(Clone, Copy, Debug)]
+struct IndexedNode {
+    point: [f64; 3],
+    node_index: NodeIndex,
+    location: Location,
+}
+
+
+/// A k-d tree over the locations of a network's nodes, for finding the nodes near a
+/// coordinate.
+///
+/// Locations are indexed as points on a sphere rather than on a map projection, as the
+/// straight line distance between two such points grows with the great circle distance
+/// along the surface. Searches are therefore exact wherever the nodes are, and results
+/// are ranked by great circle distance in metres.
+pub struct SpatialIndex {
+    /// The nodes laid out as an implicit tree, where the root of each range is its middle.
+    nodes: Vec<IndexedNode>,
+}
+
+
+impl SpatialIndex {
+
+    /// Index every node of the network.
+    pub fn new(network: &RoadNetwork) -> Self {
+        SpatialIndex::from_nodes(network.nodes_iter().map(|(_, node)| node))
+    }
+
+
+    /// Index only the nodes of the network's largest strongly connected component, so
+    /// that routes can be found between any two nodes the index returns.
+    ///
+    /// Networks which have had `reduce_to_largest_strongly_connected_component` called
+    /// only have those nodes, so `new` gives the same index more cheaply.
+    pub fn largest_component(network: &RoadNetwork) -> Self {
+        let largest: HashSet<_> = strongly_connected_components(network)
+            .into_iter()
+            .max_by_key(|component| component.len())
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        SpatialIndex::from_nodes(network.nodes_iter()
+            .filter(|(node_index, _)| largest.contains(node_index))
+            .map(|(_, node)| node))
+    }
+
+
+    fn from_nodes<'a, I: Iterator<Item=&'a Node>>(nodes: I) -> Self {
+        let mut nodes: Vec<_> = nodes
+            .map(|node| IndexedNode {
+                point: to_point(node.location.lat(), node.location.lng()),
+                node_index: node.id,
+                location: node.location,
+            })
+            .collect();
+        nodes.sort_by_key(|node| node.node_index);
+        build(&mut nodes, 0);
+        SpatialIndex {nodes}
+    }
+
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+
+    /// The node closest to a coordinate, or None if the index is empty.
+    pub fn nearest_node(&self, lat: f64, lng: f64) -> Option<NodeIndex> {
+        self.k_nearest(lat, lng, 1).first().map(|(node_index, _)| *node_index)
+    }
+
+
+    /// The `k` nodes closest to a coordinate with their distances, closest first.
+    pub fn k_nearest(&self, lat: f64, lng: f64, k: usize) -> Vec<(NodeIndex, f64)> {
+        let query = to_point(lat, lng);
+        let mut nearest = Vec::with_capacity(k + 1);
+        if k > 0 {
+            self.search_nearest(0, self.nodes.len(), 0, query, k, &mut nearest);
+        }
+        self.with_distances(lat, lng, nearest.into_iter().map(|(_, position)| position))
+    }
+
+
+    /// Every node within the radius in metres of a coordinate with its distance, closest first.
+    pub fn within_radius(&self, lat: f64, lng: f64, radius: f64) -> Vec<(NodeIndex, f64)> {
+        let query = to_point(lat, lng);
+        // The straight line through the earth to a point at the radius, a little longer to
+        // allow for rounding, with each great circle distance checked afterwards.
+        let chord = 2. * EARTH_RADIUS_METERS * (radius.min(PI * EARTH_RADIUS_METERS) / (2. * EARTH_RADIUS_METERS)).sin();
+        let search_radius = chord * (1. + 1e-9) + 1e-3;
+        let mut found = Vec::new();
+        self.search_radius(0, self.nodes.len(), 0, query, search_radius, &mut found);
+
+        let mut results = self.with_distances(lat, lng, found.into_iter());
+        results.retain(|(_, distance)| *distance <= radius);
+        results
+    }
+
+
+    fn with_distances<I: Iterator<Item=usize>>(&self, lat: f64, lng: f64, positions: I) -> Vec<(NodeIndex, f64)> {
+        let location = Location::new(lat, lng);
+        let mut results: Vec<_> = positions
+            .map(|position| {
+                let node = &self.nodes[position];
+                (node.node_index, earth_distance(&location, &node.location))
+            })
+            .collect();
+        results.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        results
+    }
+
+
+    /// Keep the `k` closest nodes seen so far in `nearest`, as squared straight line
+    /// distances and positions in `nodes`, sorted closest first.
+    fn search_nearest(&self,
+                      start: usize,
+                      end: usize,
+                      depth: usize,
+                      query: [f64; 3],
+                      k: usize,
+                      nearest: &mut Vec<(f64, usize)>) {
+        if start >= end {
+            return;
+        }
+        let middle = (start + end) / 2;
+        let node = &self.nodes[middle];
+
+        let squared_distance = squared_distance(node.point, query);
+        if nearest.len() < k || squared_distance < nearest[nearest.len() - 1].0 {
+            let position = nearest.partition_point(|(other, _)| *other <= squared_distance);
+            nearest.insert(position, (squared_distance, middle));
+            nearest.truncate(k);
+        }
+
+        let difference = query[depth % 3] - node.point[depth % 3];
+        let (near, far) = if difference < 0. {
+            ((start, middle), (middle + 1, end))
+        } else {
+            ((middle + 1, end), (start, middle))
+        };
+        self.search_nearest(near.0, near.1, depth + 1, query, k, nearest);
+        if nearest.len() < k || difference.powi(2) < nearest[nearest.len() - 1].0 {
+            self.search_nearest(far.0, far.1, depth + 1, query, k, nearest);
+        }
+    }
+
+
+    fn search_radius(&self,
+                     start: usize,
+                     end: usize,
+                     depth: usize,
+                     query: [f64; 3],
+                     radius: f64,
+                     found: &mut Vec<usize>) {
+        if start >= end {
+            return;
+        }
+        let middle = (start + end) / 2;
+        let node = &self.nodes[middle];
+
+        if squared_distance(node.point, query) <= radius.powi(2) {
+            found.push(middle);
+        }
+
+        let difference = query[depth % 3] - node.point[depth % 3];
+        if difference - radius <= 0. {
+            self.search_radius(start, middle, depth + 1, query, radius, found);
+        }
+        if difference + radius >= 0. {
+            self.search_radius(middle + 1, end, depth + 1, query, radius, found);
+        }
+    }
+}
+
+
+/// A coordinate as a point in space, with the centre of the earth at the origin.
+fn to_point(lat: f64, lng: f64) -> [f64; 3] {
+    let (lat, lng) = (lat.to_radians(), lng.to_radians());
+    [
+        EARTH_RADIUS_METERS * lat.cos() * lng.cos(),
+        EARTH_RADIUS_METERS * lat.cos() * lng.sin(),
+        EARTH_RADIUS_METERS * lat.sin(),
+    ]
+}
+
+
+fn squared_distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a.iter().zip(b.iter()).map(|(a, b)| (a - b).powi(2)).sum()
+}
+
+
+/// Arrange the nodes so the middle of every range splits the rest of it on its axis,
+/// cycling through the three.
+fn build(nodes: &mut [IndexedNode], depth: usize) {
+    if nodes.len() <= 1 {
+        return;
+    }
+    let middle = nodes.len() / 2;
+    nodes.select_nth_unstable_by(middle, |a, b| {
+        a.point[depth % 3].total_cmp(&b.point[depth % 3])
+    });
+    let (lower, upper) = nodes.split_at_mut(middle);
+    build(lower, depth + 1);
+    build(&mut upper[1..], depth + 1);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::spatial_index::*;
+    use crate::test_utils::build_grid_network;
+
+
+    /// Coordinates spread over and around the test grid.
+    fn query_points() -> Vec<(f64, f64)> {
+        let mut state: u64 = 12345;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 11) as f64 / (1u64 << 53) as f64
+        };
+        (0..50).map(|_| (48.999 + 0.01 * next(), 6.998 + 0.014 * next())).collect()
+    }
+
+
+    fn brute_force(network: &RoadNetwork, lat: f64, lng: f64) -> Vec<(NodeIndex, f64)> {
+        let location = Location::new(lat, lng);
+        let mut distances: Vec<_> = network.nodes_iter()
+            .map(|(node_index, node)| (*node_index, earth_distance(&location, &node.location)))
+            .collect();
+        distances.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        distances
+    }
+
+
+    #[test]
+    fn test_nearest_node() {
+        let network = build_grid_network(8, 8);
+        let index = SpatialIndex::new(&network);
+        assert_eq!(64, index.len());
+        assert_eq!(Some(0), index.nearest_node(49.0004, 7.0006));
+        assert_eq!(Some(63), index.nearest_node(50.0, 8.0));
+
+        for (lat, lng) in query_points() {
+            assert_eq!(Some(brute_force(&network, lat, lng)[0].0), index.nearest_node(lat, lng));
+        }
+    }
+
+
+    #[test]
+    fn test_k_nearest() {
+        let network = build_grid_network(8, 8);
+        let index = SpatialIndex::new(&network);
+
+        for (lat, lng) in query_points() {
+            let expected = brute_force(&network, lat, lng);
+            let nearest = index.k_nearest(lat, lng, 5);
+            assert_eq!(5, nearest.len());
+            for (found, expected) in nearest.iter().zip(expected.iter()) {
+                assert!((found.1 - expected.1).abs() < 1e-6);
+            }
+        }
+        assert!(index.k_nearest(49., 7., 0).is_empty());
+        assert_eq!(64, index.k_nearest(49., 7., 100).len());
+    }
+
+
+    #[test]
+    fn test_within_radius() {
+        let network = build_grid_network(8, 8);
+        let index = SpatialIndex::new(&network);
+
+        for (lat, lng) in query_points() {
+            let expected: Vec<_> = brute_force(&network, lat, lng)
+                .into_iter()
+                .filter(|(_, distance)| *distance <= 250.)
+                .map(|(node_index, _)| node_index)
+                .collect();
+            let found: Vec<_> = index.within_radius(lat, lng, 250.)
+                .into_iter()
+                .map(|(node_index, _)| node_index)
+                .collect();
+            assert_eq!(expected, found);
+        }
+    }
+
+
+    #[test]
+    fn test_network_spanning_many_degrees() {
+        // Nodes from 47 to 56 degrees north, where any one map projection would stretch
+        // east-west distances at one end or the other by several percent.
+        let mut network = RoadNetwork::new();
+        let mut state: u64 = 54321;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 11) as f64 / (1u64 << 53) as f64
+        };
+        for node_index in 0..500 {
+            network.add_node(Node::new(node_index, Location::new(47. + 9. * next(), 5. + 10. * next()))).unwrap();
+        }
+        let index = SpatialIndex::new(&network);
+
+        for (lat, lng) in (0..50).map(|_| (47. + 9. * next(), 5. + 10. * next())) {
+            let expected = brute_force(&network, lat, lng);
+            assert_eq!(Some(expected[0].0), index.nearest_node(lat, lng));
+
+            let nearest = index.k_nearest(lat, lng, 5);
+            for (found, expected) in nearest.iter().zip(expected.iter()) {
+                assert!((found.1 - expected.1).abs() < 1e-6);
+            }
+
+            let expected: Vec<_> = expected.into_iter()
+                .filter(|(_, distance)| *distance <= 100000.)
+                .map(|(node_index, _)| node_index)
+                .collect();
+            let found: Vec<_> = index.within_radius(lat, lng, 100000.)
+                .into_iter()
+                .map(|(node_index, _)| node_index)
+                .collect();
+            assert_eq!(expected, found);
+        }
+    }
+
+
+    #[test]
+    fn test_largest_component() {
+        let mut network = build_grid_network(3, 3);
+        network.add_node(Node::new(99, Location::new(49.0, 7.0))).unwrap();
+
+        assert_eq!(Some(0), SpatialIndex::largest_component(&network).nearest_node(49.0, 7.0));
+        assert_eq!(9, SpatialIndex::largest_component(&network).len());
+        assert_eq!(10, SpatialIndex::new(&network).len());
+        assert!(SpatialIndex::new(&RoadNetwork::new()).nearest_node(49.0, 7.0).is_none());
+    }
+}
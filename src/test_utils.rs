@@ -0,0 +1,47 @@
+use crate::geo_utils::{earth_distance, Location};
+use crate::osm_reader::HighwayType;
+use crate::road_network::{Cost, Node, NodeIndex, RoadNetwork};
+
+
+/// Builds a grid of nodes roughly 100m apart with two way roads between neighbouring nodes.
+///
+/// Roads around the outside of the grid are primary roads, all others are residential.
+/// Nodes are numbered row by row starting from zero.
+pub fn build_grid_network(rows: u64, cols: u64) -> RoadNetwork {
+    let mut network = RoadNetwork::new();
+
+    for i in 0..rows {
+        for j in 0..cols {
+            let location = Location::new(49.0 + 0.001 * i as f64, 7.0 + 0.0014 * j as f64);
+            network.add_node(Node::new(cols * i + j, location)).unwrap();
+        }
+    }
+
+    let highway_type = |on_outside| {
+        if on_outside { HighwayType::Primary } else { HighwayType::Residential }
+    };
+
+    for i in 0..rows {
+        for j in 0..cols {
+            let node = cols * i + j;
+            if j + 1 < cols {
+                add_road(&mut network, node, node + 1, highway_type(i == 0 || i + 1 == rows));
+            }
+            if i + 1 < rows {
+                add_road(&mut network, node, node + cols, highway_type(j == 0 || j + 1 == cols));
+            }
+        }
+    }
+
+    network
+}
+
+
+/// Adds a two way road between two nodes with a cost and distance computed the same way as the builder.
+pub fn add_road(network: &mut RoadNetwork, from: NodeIndex, to: NodeIndex, highway_type: HighwayType) {
+    let distance = earth_distance(&network.get_node(from).unwrap().location,
+                                  &network.get_node(to).unwrap().location);
+    let cost = (distance / highway_type.speed_ms()).ceil() as Cost;
+    network.add_edge_with_distance(from, to, cost, distance.round() as u64);
+    network.add_edge_with_distance(to, from, cost, distance.round() as u64);
+}
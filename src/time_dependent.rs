@@ -0,0 +1,467 @@
+use crate::osm_reader::OsmWay;
+use crate::road_network::{Cost, RoadNetwork, Node, NodeIndex};
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+
+pub const SECONDS_PER_DAY: Cost = 24 * 60 * 60;
+
+
+/// A piecewise linear travel time over the day, repeating every day.
+///
+/// Between the points the travel time is interpolated linearly, wrapping around from
+/// the last point of one day to the first point of the next.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TravelTimeFunction {
+    /// Pairs of seconds since midnight and the travel time when departing then.
+    points: Vec<(Cost, Cost)>,
+}
+
+
+impl TravelTimeFunction {
+
+    /// Build a function from points sorted by time of day.
+    ///
+    /// Fails unless the function is FIFO, that is leaving later never means arriving
+    /// earlier, which is what allows a time dependent Dijkstra to settle nodes for good.
+    pub fn new(points: Vec<(Cost, Cost)>) -> Result<Self, Box<dyn Error>> {
+        if points.is_empty() {
+            return Err(From::from("A travel time function needs at least one point"));
+        }
+        for (time, _) in points.iter() {
+            if *time >= SECONDS_PER_DAY {
+                return Err(From::from(format!("Time of day {} is not within a day", time)));
+            }
+        }
+        for (a, b) in points.iter().zip(points.iter().skip(1)) {
+            if a.0 >= b.0 {
+                return Err(From::from(format!("Times of day {} and {} are not increasing", a.0, b.0)));
+            }
+        }
+
+        let wrap_around = (points[0].0 + SECONDS_PER_DAY, points[0].1);
+        let last = points[points.len() - 1];
+        for (a, b) in points.iter().zip(points.iter().skip(1)).chain(std::iter::once((&last, &wrap_around))) {
+            if a.1 > b.1 + (b.0 - a.0) {
+                return Err(From::from(format!(
+                    "Travel time function is not FIFO, leaving at {} arrives after leaving at {}", a.0, b.0)));
+            }
+        }
+
+        Ok(TravelTimeFunction {points})
+    }
+
+
+    pub fn constant(travel_time: Cost) -> Self {
+        TravelTimeFunction {points: vec![(0, travel_time)]}
+    }
+
+
+    /// The travel time when departing at the given time, which may be on any day.
+    pub fn travel_time(&self, departure_time: Cost) -> Cost {
+        let time = departure_time % SECONDS_PER_DAY;
+
+        let next_index = self.points.iter().position(|(point_time, _)| *point_time > time);
+        let (before, after, time) = match next_index {
+            Some(0) => {
+                let last = self.points[self.points.len() - 1];
+                (last, self.points[0], time + SECONDS_PER_DAY)
+            },
+            Some(index) => (self.points[index - 1], self.points[index], time),
+            None => {
+                let first = self.points[0];
+                (self.points[self.points.len() - 1], first, time)
+            },
+        };
+
+        // Either both times are within the same day, or `after` is on the next day.
+        let (before_time, after_time) = if after.0 > before.0 {
+            (before.0, after.0)
+        } else {
+            (before.0, after.0 + SECONDS_PER_DAY)
+        };
+        let (before_time, time) = if time >= before_time {
+            (before_time, time)
+        } else {
+            (before_time, time + SECONDS_PER_DAY)
+        };
+        if after_time == before_time {
+            return before.1;
+        }
+
+        let change = after.1 as i64 - before.1 as i64;
+        let elapsed = (time - before_time) as i64;
+        let duration = (after_time - before_time) as i64;
+        (before.1 as i64 + (change * elapsed).div_euclid(duration)) as Cost
+    }
+}
+
+
+/// A path found by a time dependent search.
+#[derive(Debug, Eq, PartialEq)]
+pub struct TimeDependentPath {
+    departure_time: Cost,
+    arrival_time: Cost,
+    path: Vec<NodeIndex>,
+}
+
+
+impl TimeDependentPath {
+
+    pub fn departure_time(&self) -> Cost {
+        self.departure_time
+    }
+
+
+    pub fn arrival_time(&self) -> Cost {
+        self.arrival_time
+    }
+
+
+    pub fn path(&self) -> &[NodeIndex] {
+        &self.path
+    }
+}
+
+
+/// The travel time along an edge when departing at the given time.
+fn edge_travel_time(cost: Cost, function: &Option<TravelTimeFunction>, departure_time: Cost) -> Cost {
+    match function {
+        Some(function) => function.travel_time(departure_time),
+        None => cost,
+    }
+}
+
+
+/// Dijkstra's algorithm on arrival times, for leaving the start at the departure time.
+///
+/// Edges without a travel time function take their usual cost at any time of day.
+pub fn time_dependent_shortest_path(network: &RoadNetwork,
+                                    start_node: &Node,
+                                    end_node: &Node,
+                                    departure_time: Cost,
+) -> Option<TimeDependentPath> {
+    let mut heap = BinaryHeap::new();
+    let mut arrival_times = HashMap::new();
+    let mut parents: HashMap<NodeIndex, NodeIndex> = HashMap::new();
+    let mut settled = HashSet::new();
+
+    arrival_times.insert(start_node.id, departure_time);
+    heap.push(Reverse((departure_time, start_node.id)));
+
+    while let Some(Reverse((time, node_index))) = heap.pop() {
+        if !settled.insert(node_index) {
+            continue;
+        }
+
+        if node_index == end_node.id {
+            let mut path = vec![node_index];
+            while let Some(parent) = parents.get(path.last().unwrap()) {
+                path.push(*parent);
+            }
+            path.reverse();
+            return Some(TimeDependentPath {departure_time, arrival_time: time, path});
+        }
+
+        let node = network.get_node(node_index).unwrap();
+        for edge in node.neighbours.iter() {
+            let arrival_time = time + edge_travel_time(edge.cost, &edge.travel_time_function, time);
+            if arrival_times.get(&edge.destination).is_none_or(|current| arrival_time < *current) {
+                arrival_times.insert(edge.destination, arrival_time);
+                parents.insert(edge.destination, node_index);
+                heap.push(Reverse((arrival_time, edge.destination)));
+            }
+        }
+    }
+
+    None
+}
+
+
+/// Which edges a profile applies to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProfileKey {
+    /// Every edge along an OSM way, in both directions.
+    Way(u64),
+    /// The edge from one node to another.
+    NodePair(NodeIndex, NodeIndex),
+}
+
+
+/// How much slower than usual travel is at times of the day.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub key: ProfileKey,
+    /// Pairs of seconds since midnight and the factor to multiply an edge's cost by.
+    pub factors: Vec<(Cost, f64)>,
+}
+
+
+impl Profile {
+
+    /// The travel time function of an edge with the given cost.
+    pub fn travel_time_function(&self, cost: Cost) -> Result<TravelTimeFunction, Box<dyn Error>> {
+        TravelTimeFunction::new(self.factors
+            .iter()
+            .map(|&(time, factor)| (time, (cost as f64 * factor).ceil() as Cost))
+            .collect())
+    }
+}
+
+
+/// Read profiles from a CSV file.
+///
+/// Each line is either `way,<way id>,<factors>...` or `nodes,<from>,<to>,<factors>...`,
+/// where each factor is written `<seconds since midnight>:<factor>`, for example
+/// `way,26659127,0:1.0,28800:1.8,36000:1.0`. Blank lines and lines starting with `#`
+/// are ignored.
+pub fn read_profiles_csv(file_name: &str) -> Result<Vec<Profile>, Box<dyn Error>> {
+    let f = File::open(file_name)?;
+    parse_profiles(BufReader::new(f))
+}
+
+
+fn parse_profiles<R: BufRead>(reader: R) -> Result<Vec<Profile>, Box<dyn Error>> {
+    let mut profiles = Vec::new();
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let profile = parse_profile(line)
+            .map_err(|e| format!("Line {} of profiles: {}", line_number + 1, e))?;
+        profiles.push(profile);
+    }
+    Ok(profiles)
+}
+
+
+fn parse_profile(line: &str) -> Result<Profile, Box<dyn Error>> {
+    let fields: Vec<_> = line.split(',').map(|field| field.trim()).collect();
+    let (key, factor_fields) = match fields[0] {
+        "way" if fields.len() > 1 => (ProfileKey::Way(fields[1].parse()?), &fields[2..]),
+        "nodes" if fields.len() > 2 => {
+            (ProfileKey::NodePair(fields[1].parse()?, fields[2].parse()?), &fields[3..])
+        },
+        kind => return Err(From::from(format!("Unknown or incomplete profile of kind '{}'", kind))),
+    };
+
+    let mut factors = Vec::with_capacity(factor_fields.len());
+    for field in factor_fields {
+        let mut parts = field.splitn(2, ':');
+        let time = parts.next().unwrap().parse()?;
+        let factor = parts.next()
+            .ok_or_else(|| format!("Expected <time>:<factor> but found '{}'", field))?
+            .parse()?;
+        factors.push((time, factor));
+    }
+
+    Ok(Profile {key, factors})
+}
+
+
+/// Set the travel time functions of the edges each profile applies to.
+///
+/// Ways are looked up in the OSM data the network was built from. Profiles for ways or
+/// edges which aren't in the network are skipped. Returns the number of edges updated,
+/// or an error without changing any edge if a profile gives an invalid function.
+pub fn apply_profiles(network: &mut RoadNetwork,
+                      ways: &[OsmWay],
+                      profiles: &[Profile]) -> Result<usize, Box<dyn Error>> {
+    let ways_by_id: HashMap<_, _> = ways.iter().map(|way| (way.id, way)).collect();
+
+    // Every function is built before any edge is changed, so a bad profile can't leave
+    // the network half updated.
+    let mut updates = Vec::new();
+    for profile in profiles.iter() {
+        let node_pairs = match profile.key {
+            ProfileKey::NodePair(from, to) => vec![(from, to)],
+            ProfileKey::Way(way_id) => match ways_by_id.get(&way_id) {
+                Some(way) => way.nodes.iter()
+                    .zip(way.nodes.iter().skip(1))
+                    .flat_map(|(a, b)| vec![(a.ref_, b.ref_), (b.ref_, a.ref_)])
+                    .collect(),
+                None => continue,
+            },
+        };
+
+        for (from, to) in node_pairs {
+            let node = match network.get_node(from) {
+                Some(node) => node,
+                None => continue,
+            };
+            for (edge_index, edge) in node.neighbours.iter().enumerate() {
+                if edge.destination == to {
+                    updates.push((from, edge_index, profile.travel_time_function(edge.cost)?));
+                }
+            }
+        }
+    }
+
+    let num_edges = updates.len();
+    for (from, edge_index, function) in updates {
+        network.get_node_mut(from).unwrap().neighbours[edge_index].travel_time_function = Some(function);
+    }
+    Ok(num_edges)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::time_dependent::*;
+    use crate::geo_utils::Location;
+    use crate::osm_reader::Osm;
+
+    use serde_xml_rs::deserialize;
+
+
+    #[test]
+    fn test_travel_time_function() {
+        let function = TravelTimeFunction::new(vec![(3600, 100), (7200, 400), (80000, 100)]).unwrap();
+
+        assert_eq!(100, function.travel_time(3600));
+        assert_eq!(250, function.travel_time(5400));
+        assert_eq!(400, function.travel_time(7200));
+        assert_eq!(100, function.travel_time(80000));
+        assert_eq!(100, function.travel_time(0));
+        assert_eq!(100, function.travel_time(SECONDS_PER_DAY + 3600));
+        assert_eq!(250, function.travel_time(2 * SECONDS_PER_DAY + 5400));
+
+        assert_eq!(30, TravelTimeFunction::constant(30).travel_time(12345));
+    }
+
+
+    #[test]
+    fn test_invalid_travel_time_functions() {
+        assert!(TravelTimeFunction::new(vec![]).is_err());
+        assert!(TravelTimeFunction::new(vec![(SECONDS_PER_DAY, 10)]).is_err());
+        assert!(TravelTimeFunction::new(vec![(100, 10), (50, 10)]).is_err());
+        // Leaving at 100 arrives at 1100, but leaving at 200 arrives at 210.
+        assert!(TravelTimeFunction::new(vec![(100, 1000), (200, 10)]).is_err());
+        // The same problem wrapping around midnight.
+        assert!(TravelTimeFunction::new(vec![(0, 10), (SECONDS_PER_DAY - 10, 1000)]).is_err());
+    }
+
+
+    fn get_test_network() -> RoadNetwork {
+        let mut network = RoadNetwork::new();
+        for i in 1..5 {
+            network.add_node(Node::new(i, Location::new(0., 0.))).unwrap();
+        }
+
+        // A fast road from 1 to 4 which is congested in the morning, and a slower one via 2 and 3.
+        network.add_edge(1, 4, 100);
+        network.add_edge(1, 2, 100);
+        network.add_edge(2, 3, 100);
+        network.add_edge(3, 4, 100);
+
+        let rush_hour = TravelTimeFunction::new(vec![(0, 100), (28000, 100), (28800, 800), (36000, 100)]).unwrap();
+        network.set_travel_time_function(1, 4, rush_hour).unwrap();
+
+        network
+    }
+
+
+    #[test]
+    fn test_time_dependent_shortest_path() {
+        let network = get_test_network();
+        let start = network.get_node(1).unwrap();
+        let end = network.get_node(4).unwrap();
+
+        let night = time_dependent_shortest_path(&network, start, end, 3600).unwrap();
+        assert_eq!(&[1, 4], night.path());
+        assert_eq!(3700, night.arrival_time());
+
+        let rush_hour = time_dependent_shortest_path(&network, start, end, 28800).unwrap();
+        assert_eq!(&[1, 2, 3, 4], rush_hour.path());
+        assert_eq!(28800, rush_hour.departure_time());
+        assert_eq!(29100, rush_hour.arrival_time());
+
+        let unreachable = network.get_node(4).unwrap();
+        assert!(time_dependent_shortest_path(&network, unreachable, start, 0).is_none());
+    }
+
+
+    #[test]
+    fn test_set_travel_time_function_on_missing_edge() {
+        let mut network = get_test_network();
+        assert!(network.set_travel_time_function(4, 1, TravelTimeFunction::constant(1)).is_err());
+        assert!(network.set_travel_time_function(99, 1, TravelTimeFunction::constant(1)).is_err());
+    }
+
+
+    #[test]
+    fn test_parse_profiles() {
+        let csv = "# kind,key,factors\n\
+                   way,26659127,0:1.0,28800:1.5\n\
+                   \n\
+                   nodes,1,4,0:2\n";
+        let profiles = parse_profiles(csv.as_bytes()).unwrap();
+
+        assert_eq!(2, profiles.len());
+        assert_eq!(ProfileKey::Way(26659127), profiles[0].key);
+        assert_eq!(vec![(0, 1.0), (28800, 1.5)], profiles[0].factors);
+        assert_eq!(ProfileKey::NodePair(1, 4), profiles[1].key);
+
+        assert!(parse_profiles("road,1,0:1".as_bytes()).is_err());
+        assert!(parse_profiles("way,1,0".as_bytes()).is_err());
+        assert!(read_profiles_csv("not a file path").is_err());
+    }
+
+
+    #[test]
+    fn test_apply_profiles() {
+        let osm: Osm = deserialize(r##"
+        <osm>
+            <way id="7">
+                <nd ref="1"/>
+                <nd ref="2"/>
+                <nd ref="3"/>
+            </way>
+        </osm>
+        "##.as_bytes()).unwrap();
+
+        let mut network = get_test_network();
+        let profiles = parse_profiles("way,7,0:1.5\nway,8,0:2\nnodes,3,4,0:2".as_bytes()).unwrap();
+        assert_eq!(3, apply_profiles(&mut network, &osm.ways, &profiles).unwrap());
+
+        let start = network.get_node(1).unwrap();
+        let end = network.get_node(4).unwrap();
+        let result = time_dependent_shortest_path(&network, start, end, 3600).unwrap();
+        assert_eq!(3700, result.arrival_time());
+
+        let edge = &network.get_node(2).unwrap().neighbours[0];
+        assert_eq!(Some(TravelTimeFunction::constant(150)), edge.travel_time_function);
+    }
+
+
+    #[test]
+    fn test_failing_profiles_leave_network_unchanged() {
+        let osm: Osm = deserialize(r##"
+        <osm>
+            <way id="7">
+                <nd ref="1"/>
+                <nd ref="2"/>
+            </way>
+        </osm>
+        "##.as_bytes()).unwrap();
+
+        let functions = |network: &RoadNetwork| -> Vec<_> {
+            (1..5)
+                .flat_map(|node_index| network.get_node(node_index).unwrap().neighbours.iter())
+                .map(|edge| edge.travel_time_function.clone())
+                .collect()
+        };
+
+        let mut network = get_test_network();
+        // The way's profile is valid, but leaving 3 at 100 would arrive after leaving at 200.
+        let profiles = parse_profiles("way,7,0:1.5\nnodes,3,4,100:20,200:0.1".as_bytes()).unwrap();
+        assert!(apply_profiles(&mut network, &osm.ways, &profiles).is_err());
+        assert_eq!(functions(&get_test_network()), functions(&network));
+    }
+}
@@ -0,0 +1,52 @@
+/// The number of days from 1970-01-01 to a date in the Gregorian calendar.
+pub fn days_since_epoch(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+
+
+/// The year, month and day of the date a number of days after 1970-01-01.
+pub fn date_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::time_utils::*;
+
+
+    #[test]
+    fn test_days_since_epoch() {
+        assert_eq!(0, days_since_epoch(1970, 1, 1));
+        assert_eq!(-1, days_since_epoch(1969, 12, 31));
+        assert_eq!(19723, days_since_epoch(2024, 1, 1));
+        assert_eq!(19783, days_since_epoch(2024, 3, 1));
+    }
+
+
+    #[test]
+    fn test_date_from_days() {
+        assert_eq!((1970, 1, 1), date_from_days(0));
+        assert_eq!((1969, 12, 31), date_from_days(-1));
+        assert_eq!((2024, 2, 29), date_from_days(19782));
+        for days in -1000..1000 {
+            let (year, month, day) = date_from_days(days * 37);
+            assert_eq!(days * 37, days_since_epoch(year, month, day));
+        }
+    }
+}
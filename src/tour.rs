@@ -0,0 +1,246 @@
+use crate::matrix::many_to_many;
+use crate::road_network::{Cost, RoadNetwork, NodeIndex};
+use crate::via_route::{route_via, ViaRoute};
+
+
+/// Where a tour finishes after visiting every stop.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TourEnd {
+    /// Finish at whichever stop is visited last.
+    Open,
+    /// Finish at the given node.
+    Fixed(NodeIndex),
+    /// Return to the start.
+    RoundTrip,
+}
+
+
+/// The stops in the order to visit them and the route which visits them.
+#[derive(Debug)]
+pub struct Tour {
+    stops: Vec<NodeIndex>,
+    route: ViaRoute,
+}
+
+
+impl Tour {
+
+    /// The stops in visiting order, not including the start or a fixed end.
+    pub fn stops(&self) -> &[NodeIndex] {
+        &self.stops
+    }
+
+
+    /// The route from the start through every stop to the end of the tour.
+    pub fn route(&self) -> &ViaRoute {
+        &self.route
+    }
+}
+
+
+/// Choose a good order to visit the stops in, starting from the start node.
+///
+/// Travel times between every pair of stops are computed up front. A tour is built
+/// by nearest insertion, then improved with 2-opt and Or-opt moves until neither finds
+/// a shorter tour. Returns None if some stop can't be reached.
+pub fn optimise_tour(network: &RoadNetwork, start: NodeIndex, stops: &[NodeIndex], end: TourEnd) -> Option<Tour> {
+    let mut nodes = vec![start];
+    nodes.extend_from_slice(stops);
+    if let TourEnd::Fixed(end_node) = end {
+        nodes.push(end_node);
+    }
+
+    let matrix = many_to_many(network, &nodes, &nodes);
+    let costs = TourCosts {
+        costs: (0..nodes.len())
+            .map(|from| matrix.row(from).iter().map(|cost| cost.unwrap_or(Cost::MAX)).collect())
+            .collect(),
+        end,
+        end_index: nodes.len() - 1,
+    };
+
+    let mut order = nearest_insertion(&costs, stops.len());
+    while two_opt(&costs, &mut order) || or_opt(&costs, &mut order) {}
+
+    if costs.tour_cost(&order) == Cost::MAX {
+        return None;
+    }
+
+    let ordered_stops: Vec<_> = order.iter().map(|index| nodes[*index]).collect();
+    let mut waypoints = vec![start];
+    waypoints.extend_from_slice(&ordered_stops);
+    match end {
+        TourEnd::Open => {},
+        TourEnd::Fixed(end_node) => waypoints.push(end_node),
+        TourEnd::RoundTrip => waypoints.push(start),
+    }
+
+    Some(Tour {stops: ordered_stops, route: route_via(network, &waypoints)?})
+}
+
+
+/// Travel times between the start (index 0), the stops and a fixed end (the last index).
+struct TourCosts {
+    costs: Vec<Vec<Cost>>,
+    end: TourEnd,
+    end_index: usize,
+}
+
+
+impl TourCosts {
+
+    fn cost(&self, from: usize, to: usize) -> Cost {
+        self.costs[from][to]
+    }
+
+
+    /// The cost of visiting the stops in order, saturating at `Cost::MAX` when unreachable.
+    fn tour_cost(&self, order: &[usize]) -> Cost {
+        let mut total: Cost = 0;
+        let mut previous = 0;
+        for stop in order.iter() {
+            total = total.saturating_add(self.cost(previous, *stop));
+            previous = *stop;
+        }
+        match self.end {
+            TourEnd::Open => total,
+            TourEnd::Fixed(_) => total.saturating_add(self.cost(previous, self.end_index)),
+            TourEnd::RoundTrip => total.saturating_add(self.cost(previous, 0)),
+        }
+    }
+}
+
+
+/// Repeatedly add the stop closest to the tour so far, where it adds least to the cost.
+fn nearest_insertion(costs: &TourCosts, num_stops: usize) -> Vec<usize> {
+    let mut order: Vec<usize> = Vec::with_capacity(num_stops);
+    let mut remaining: Vec<usize> = (1..=num_stops).collect();
+
+    while !remaining.is_empty() {
+        let (remaining_index, _) = remaining.iter()
+            .enumerate()
+            .map(|(remaining_index, stop)| {
+                let closest = order.iter()
+                    .chain(std::iter::once(&0))
+                    .map(|visited| costs.cost(*visited, *stop).min(costs.cost(*stop, *visited)))
+                    .min()
+                    .unwrap();
+                (remaining_index, closest)
+            })
+            .min_by_key(|&(remaining_index, closest)| (closest, remaining_index))
+            .unwrap();
+        let stop = remaining.remove(remaining_index);
+
+        let position = (0..=order.len())
+            .min_by_key(|position| {
+                let mut candidate = order.clone();
+                candidate.insert(*position, stop);
+                costs.tour_cost(&candidate)
+            })
+            .unwrap();
+        order.insert(position, stop);
+    }
+
+    order
+}
+
+
+/// Reverse the first section of the tour that makes it cheaper, returning whether one was found.
+fn two_opt(costs: &TourCosts, order: &mut [usize]) -> bool {
+    let current = costs.tour_cost(order);
+    for i in 0..order.len() {
+        for j in i + 1..order.len() {
+            order[i..=j].reverse();
+            if costs.tour_cost(order) < current {
+                return true;
+            }
+            order[i..=j].reverse();
+        }
+    }
+    false
+}
+
+
+/// Move the first run of up to three stops elsewhere in the tour that makes it cheaper,
+/// returning whether one was found.
+fn or_opt(costs: &TourCosts, order: &mut Vec<usize>) -> bool {
+    let current = costs.tour_cost(order);
+    for length in 1..=3.min(order.len()) {
+        for from in 0..=order.len() - length {
+            let mut rest = order.clone();
+            let segment: Vec<_> = rest.drain(from..from + length).collect();
+
+            for to in 0..=rest.len() {
+                if to == from {
+                    continue;
+                }
+                let mut candidate = rest.clone();
+                candidate.splice(to..to, segment.iter().cloned());
+                if costs.tour_cost(&candidate) < current {
+                    *order = candidate;
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::tour::*;
+    use crate::geo_utils::Location;
+    use crate::road_network::Node;
+    use crate::test_utils::build_grid_network;
+
+
+    #[test]
+    fn test_open_tour_visits_stops_along_the_way() {
+        let network = build_grid_network(1, 6);
+        let tour = optimise_tour(&network, 0, &[4, 2, 5, 1], TourEnd::Open).unwrap();
+
+        assert_eq!(&[1, 2, 4, 5], tour.stops());
+        assert_eq!(&[0, 1, 2, 3, 4, 5], tour.route().route().path());
+    }
+
+
+    #[test]
+    fn test_fixed_end() {
+        let network = build_grid_network(1, 6);
+        let tour = optimise_tour(&network, 2, &[4, 1], TourEnd::Fixed(5)).unwrap();
+
+        assert_eq!(&[1, 4], tour.stops());
+        assert_eq!(&[2, 1, 2, 3, 4, 5], tour.route().route().path());
+    }
+
+
+    #[test]
+    fn test_round_trip_is_no_worse_than_given_order() {
+        let network = build_grid_network(4, 4);
+        let stops = [15, 0, 12, 3, 5, 10];
+        let tour = optimise_tour(&network, 6, &stops, TourEnd::RoundTrip).unwrap();
+
+        let mut visited = tour.stops().to_vec();
+        visited.sort();
+        assert_eq!(vec![0, 3, 5, 10, 12, 15], visited);
+
+        let path = tour.route().route().path();
+        assert_eq!(Some(&6), path.first());
+        assert_eq!(Some(&6), path.last());
+
+        let mut given_order = vec![6];
+        given_order.extend_from_slice(&stops);
+        given_order.push(6);
+        let given = route_via(&network, &given_order).unwrap();
+        assert!(tour.route().route().cost() <= given.route().cost());
+    }
+
+
+    #[test]
+    fn test_unreachable_stop() {
+        let mut network = build_grid_network(2, 2);
+        network.add_node(Node::new(99, Location::new(0., 0.))).unwrap();
+        assert!(optimise_tour(&network, 0, &[3, 99], TourEnd::Open).is_none());
+    }
+}
@@ -0,0 +1,725 @@
+use crate::gtfs::{previous_date, GtfsFeed, ServiceDate, Stop, StopTime};
+use crate::road_network::{Cost, RoadNetwork, NodeIndex};
+use crate::shortest_path::Direction;
+use crate::spatial_index::SpatialIndex;
+use crate::time_dependent::SECONDS_PER_DAY;
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::error::Error;
+
+
+/// A vehicle travelling from one stop to the next without stopping in between.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Connection {
+    pub departure_stop: usize,
+    pub arrival_stop: usize,
+    pub departure_time: Cost,
+    pub arrival_time: Cost,
+    pub trip: usize,
+}
+
+
+/// How fast and how far people are willing to walk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WalkingParameters {
+    pub speed_ms: f64,
+    /// The longest walk in seconds between stops, or to or from a stop.
+    pub max_duration: Cost,
+}
+
+
+impl Default for WalkingParameters {
+    fn default() -> Self {
+        WalkingParameters {speed_ms: 1.4, max_duration: 600}
+    }
+}
+
+
+impl WalkingParameters {
+
+    fn duration(&self, distance_meters: u64) -> Cost {
+        (distance_meters as f64 / self.speed_ms).ceil() as Cost
+    }
+
+
+    fn max_distance(&self) -> u64 {
+        (self.max_duration as f64 * self.speed_ms) as u64
+    }
+}
+
+
+/// Part of a journey. Stops are indices into the timetable's stops.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Leg {
+    Ride {
+        trip_id: String,
+        from_stop: usize,
+        to_stop: usize,
+        departure_time: Cost,
+        arrival_time: Cost,
+    },
+    /// A walk between stops, where a missing stop is the node the journey starts or ends at.
+    Walk {
+        from_stop: Option<usize>,
+        to_stop: Option<usize>,
+        departure_time: Cost,
+        arrival_time: Cost,
+    },
+}
+
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Journey {
+    departure_time: Cost,
+    arrival_time: Cost,
+    legs: Vec<Leg>,
+}
+
+
+impl Journey {
+
+    /// The time the journey was asked to start, which may be before the first leg leaves.
+    pub fn departure_time(&self) -> Cost {
+        self.departure_time
+    }
+
+
+    pub fn arrival_time(&self) -> Cost {
+        self.arrival_time
+    }
+
+
+    pub fn legs(&self) -> &[Leg] {
+        &self.legs
+    }
+}
+
+
+/// How the connection scan first reached a stop.
+#[derive(Clone, Copy, Debug)]
+enum Reached {
+    Ride { boarded: usize, alighted: usize },
+    Walk { from_stop: Option<usize>, departure_time: Cost },
+}
+
+
+/// The trips running on one day, as connections sorted by departure time.
+pub struct Timetable {
+    stops: Vec<Stop>,
+    stop_indices: HashMap<String, usize>,
+    trip_ids: Vec<String>,
+    connections: Vec<Connection>,
+    /// The stops reachable on foot from each stop, and how long it takes to walk there.
+    transfers: Vec<Vec<(usize, Cost)>>,
+    /// The nearest road node to each stop, and how many metres away it is.
+    stop_links: Vec<Option<(NodeIndex, u64)>>,
+    node_stops: HashMap<NodeIndex, Vec<usize>>,
+    walking: Option<WalkingParameters>,
+}
+
+
+impl Timetable {
+
+    /// Build the timetable of the trips in the feed which run on the date, along with
+    /// the rest of the trips of the day before which run past midnight.
+    ///
+    /// Times are seconds since midnight of the date.
+    pub fn new(feed: &GtfsFeed, date: ServiceDate) -> Result<Self, Box<dyn Error>> {
+        let stop_indices: HashMap<_, _> = feed.stops.iter()
+            .enumerate()
+            .map(|(index, stop)| (stop.id.clone(), index))
+            .collect();
+
+        let mut trip_stop_times: HashMap<&str, Vec<&StopTime>> = HashMap::new();
+        for stop_time in feed.stop_times.iter() {
+            if !stop_indices.contains_key(&stop_time.stop_id) {
+                return Err(From::from(format!("Trip {} calls at unknown stop {}",
+                                              stop_time.trip_id, stop_time.stop_id)));
+            }
+            trip_stop_times.entry(stop_time.trip_id.as_str()).or_default().push(stop_time);
+        }
+
+        // Trips of the day before which run past midnight still have connections today.
+        let mut trip_ids = Vec::new();
+        let mut connections = Vec::new();
+        for (service_date, shift) in [(previous_date(date), SECONDS_PER_DAY), (date, 0)] {
+            for trip in feed.trips.iter().filter(|trip| feed.service_runs_on(&trip.service_id, service_date)) {
+                let stop_times = match trip_stop_times.get_mut(trip.id.as_str()) {
+                    Some(stop_times) => stop_times,
+                    None => continue,
+                };
+                stop_times.sort_by_key(|stop_time| stop_time.stop_sequence);
+
+                let num_connections = connections.len();
+                let times = interpolate_times(stop_times);
+                for (from, to) in times.iter().zip(times.iter().skip(1)) {
+                    if from.2 < shift {
+                        continue;
+                    }
+                    connections.push(Connection {
+                        departure_stop: stop_indices[from.0],
+                        arrival_stop: stop_indices[to.0],
+                        departure_time: from.2 - shift,
+                        arrival_time: to.1 - shift,
+                        trip: trip_ids.len(),
+                    });
+                }
+                if connections.len() > num_connections {
+                    trip_ids.push(trip.id.clone());
+                }
+            }
+        }
+        connections.sort_by_key(|connection| (connection.departure_time, connection.arrival_time));
+
+        Ok(Timetable {
+            stops: feed.stops.clone(),
+            stop_indices,
+            trip_ids,
+            connections,
+            transfers: vec![Vec::new(); feed.stops.len()],
+            stop_links: vec![None; feed.stops.len()],
+            node_stops: HashMap::new(),
+            walking: None,
+        })
+    }
+
+
+    pub fn stops(&self) -> &[Stop] {
+        &self.stops
+    }
+
+
+    pub fn stop_index(&self, stop_id: &str) -> Option<usize> {
+        self.stop_indices.get(stop_id).cloned()
+    }
+
+
+    pub fn connections(&self) -> &[Connection] {
+        &self.connections
+    }
+
+
+    /// Allow walking from one stop to another, taking the given number of seconds.
+    pub fn add_transfer(&mut self, from_stop: usize, to_stop: usize, duration: Cost) {
+        self.transfers[from_stop].push((to_stop, duration));
+    }
+
+
+    /// The road node a stop was linked to by `link_to_road_network`.
+    pub fn stop_node(&self, stop: usize) -> Option<NodeIndex> {
+        self.stop_links[stop].map(|(node_index, _)| node_index)
+    }
+
+
+    /// Link every stop to its nearest road node and add transfers between stops within
+    /// walking distance of each other along the roads.
+    ///
+    /// Walking uses the length of the road edges, so needs a network with edge distances.
+    pub fn link_to_road_network(&mut self, network: &RoadNetwork, walking: WalkingParameters) {
+        let spatial_index = SpatialIndex::new(network);
+        for (stop_index, stop) in self.stops.iter().enumerate() {
+            let nearest = spatial_index.k_nearest(stop.location.lat(), stop.location.lng(), 1);
+            if let Some(&(node_index, distance)) = nearest.first() {
+                self.stop_links[stop_index] = Some((node_index, distance.round() as u64));
+                self.node_stops.entry(node_index).or_default().push(stop_index);
+            }
+        }
+        self.walking = Some(walking);
+
+        for from_stop in 0..self.stops.len() {
+            let (from_node, from_link) = match self.stop_links[from_stop] {
+                Some(link) => link,
+                None => continue,
+            };
+            let distances = walking_distances(network, from_node, Direction::Forward, walking.max_distance());
+            for (to_stop, distance) in self.stops_near(&distances) {
+                let duration = walking.duration(from_link + distance);
+                if to_stop != from_stop && duration <= walking.max_duration {
+                    self.transfers[from_stop].push((to_stop, duration));
+                }
+            }
+        }
+    }
+
+
+    /// The stops linked to the nodes, with the walking distance to the stop.
+    fn stops_near(&self, node_distances: &HashMap<NodeIndex, u64>) -> Vec<(usize, u64)> {
+        node_distances.iter()
+            .filter_map(|(node_index, distance)| self.node_stops.get(node_index).map(|stops| (stops, distance)))
+            .flat_map(|(stops, distance)| stops.iter().map(move |stop| (*stop, distance + self.stop_links[*stop].unwrap().1)))
+            .collect()
+    }
+
+
+    /// The journey from one stop to another arriving earliest, with the Connection Scan Algorithm.
+    pub fn earliest_arrival(&self, source: usize, target: usize, departure_time: Cost) -> Option<Journey> {
+        let mut origins = vec![(source, 0, None)];
+        origins.extend(self.transfers[source].iter().map(|&(stop, duration)| (stop, duration, Some(source))));
+        self.scan(departure_time, &origins, &[(target, 0)], None)
+    }
+
+
+    /// The journey between two road nodes arriving earliest, walking to the first stop and
+    /// from the last, or walking all the way if that is quicker.
+    ///
+    /// Returns None unless the timetable has been linked to the road network.
+    pub fn earliest_arrival_between_nodes(&self,
+                                          network: &RoadNetwork,
+                                          from: NodeIndex,
+                                          to: NodeIndex,
+                                          departure_time: Cost) -> Option<Journey> {
+        let walking = self.walking?;
+        let from_distances = walking_distances(network, from, Direction::Forward, walking.max_distance());
+        let to_distances = walking_distances(network, to, Direction::Backward, walking.max_distance());
+
+        let within_walking = |(stop, distance): (usize, u64)| {
+            Some((stop, walking.duration(distance))).filter(|(_, duration)| *duration <= walking.max_duration)
+        };
+        let origins: Vec<_> = self.stops_near(&from_distances)
+            .into_iter()
+            .filter_map(within_walking)
+            .map(|(stop, duration)| (stop, duration, None))
+            .collect();
+        let destinations: Vec<_> = self.stops_near(&to_distances)
+            .into_iter()
+            .filter_map(within_walking)
+            .collect();
+        let direct_walk = from_distances.get(&to).map(|distance| walking.duration(*distance));
+
+        self.scan(departure_time, &origins, &destinations, direct_walk)
+    }
+
+
+    /// The connection scan from stops reached on foot at the start, to whichever of the
+    /// destination stops gives the earliest arrival after walking on from it.
+    fn scan(&self,
+            departure_time: Cost,
+            origins: &[(usize, Cost, Option<usize>)],
+            destinations: &[(usize, Cost)],
+            direct_walk: Option<Cost>) -> Option<Journey> {
+        let mut arrival_times = vec![Cost::MAX; self.stops.len()];
+        let mut reached: Vec<Option<Reached>> = vec![None; self.stops.len()];
+        let mut boarded: Vec<Option<usize>> = vec![None; self.trip_ids.len()];
+
+        let mut final_walks: HashMap<usize, Cost> = HashMap::new();
+        for &(stop, duration) in destinations.iter() {
+            let walk = final_walks.entry(stop).or_insert(duration);
+            *walk = duration.min(*walk);
+        }
+
+        // The earliest arrival so far, and the stop walked on from or None if walking directly.
+        let mut best = direct_walk.map(|duration| (departure_time + duration, None));
+        let update_best = |stop: usize, arrival_time: Cost, best: &mut Option<(Cost, Option<usize>)>| {
+            if let Some(walk) = final_walks.get(&stop) {
+                if best.is_none_or(|(best_time, _)| arrival_time + walk < best_time) {
+                    *best = Some((arrival_time + walk, Some(stop)));
+                }
+            }
+        };
+
+        for &(stop, duration, from_stop) in origins.iter() {
+            if departure_time + duration < arrival_times[stop] {
+                arrival_times[stop] = departure_time + duration;
+                reached[stop] = Some(Reached::Walk {from_stop, departure_time});
+                update_best(stop, arrival_times[stop], &mut best);
+            }
+        }
+
+        let first = self.connections.partition_point(|connection| connection.departure_time < departure_time);
+        for (index, connection) in self.connections.iter().enumerate().skip(first) {
+            if best.is_some_and(|(best_time, _)| best_time <= connection.departure_time) {
+                break;
+            }
+            if boarded[connection.trip].is_none() && arrival_times[connection.departure_stop] <= connection.departure_time {
+                boarded[connection.trip] = Some(index);
+            }
+            let boarded_index = match boarded[connection.trip] {
+                Some(boarded_index) => boarded_index,
+                None => continue,
+            };
+
+            let stop = connection.arrival_stop;
+            if connection.arrival_time >= arrival_times[stop] {
+                continue;
+            }
+            arrival_times[stop] = connection.arrival_time;
+            reached[stop] = Some(Reached::Ride {boarded: boarded_index, alighted: index});
+            update_best(stop, connection.arrival_time, &mut best);
+
+            for &(to_stop, duration) in self.transfers[stop].iter() {
+                let arrival_time = connection.arrival_time + duration;
+                if arrival_time < arrival_times[to_stop] {
+                    arrival_times[to_stop] = arrival_time;
+                    reached[to_stop] = Some(Reached::Walk {
+                        from_stop: Some(stop),
+                        departure_time: connection.arrival_time,
+                    });
+                    update_best(to_stop, arrival_time, &mut best);
+                }
+            }
+        }
+
+        let (arrival_time, last_stop) = best?;
+        let mut legs = Vec::new();
+        let mut stop = match last_stop {
+            Some(stop) => stop,
+            None => {
+                legs.push(Leg::Walk {from_stop: None, to_stop: None, departure_time, arrival_time});
+                return Some(Journey {departure_time, arrival_time, legs});
+            },
+        };
+        if arrival_time > arrival_times[stop] {
+            legs.push(Leg::Walk {
+                from_stop: Some(stop),
+                to_stop: None,
+                departure_time: arrival_times[stop],
+                arrival_time,
+            });
+        }
+
+        loop {
+            match reached[stop].unwrap() {
+                Reached::Ride {boarded, alighted} => {
+                    let boarded = self.connections[boarded];
+                    legs.push(Leg::Ride {
+                        trip_id: self.trip_ids[boarded.trip].clone(),
+                        from_stop: boarded.departure_stop,
+                        to_stop: stop,
+                        departure_time: boarded.departure_time,
+                        arrival_time: self.connections[alighted].arrival_time,
+                    });
+                    stop = boarded.departure_stop;
+                },
+                Reached::Walk {from_stop, departure_time: walk_departure_time} => {
+                    if arrival_times[stop] > walk_departure_time {
+                        legs.push(Leg::Walk {
+                            from_stop,
+                            to_stop: Some(stop),
+                            departure_time: walk_departure_time,
+                            arrival_time: arrival_times[stop],
+                        });
+                    }
+                    match from_stop {
+                        Some(from_stop) if from_stop != stop => stop = from_stop,
+                        _ => break,
+                    }
+                },
+            }
+        }
+
+        legs.reverse();
+        Some(Journey {departure_time, arrival_time, legs})
+    }
+
+
+    /// Every journey from one stop to another leaving within a time window which no other
+    /// journey beats by leaving later and arriving no later, as pairs of departure and
+    /// arrival time sorted by departure.
+    ///
+    /// Uses the profile variant of the Connection Scan Algorithm, scanning connections from
+    /// the latest departure to the earliest.
+    pub fn profile(&self,
+                   source: usize,
+                   target: usize,
+                   earliest_departure: Cost,
+                   latest_departure: Cost) -> Vec<(Cost, Cost)> {
+        let mut profiles: Vec<Vec<(Cost, Cost)>> = vec![Vec::new(); self.stops.len()];
+        let mut trip_arrivals = vec![Cost::MAX; self.trip_ids.len()];
+
+        let mut incoming_transfers: Vec<Vec<(usize, Cost)>> = vec![Vec::new(); self.stops.len()];
+        let mut final_walks: HashMap<usize, Cost> = HashMap::new();
+        final_walks.insert(target, 0);
+        for (from_stop, transfers) in self.transfers.iter().enumerate() {
+            for &(to_stop, duration) in transfers.iter() {
+                incoming_transfers[to_stop].push((from_stop, duration));
+                if to_stop == target && from_stop != target {
+                    let walk = final_walks.entry(from_stop).or_insert(duration);
+                    *walk = duration.min(*walk);
+                }
+            }
+        }
+
+        for connection in self.connections.iter().rev() {
+            if connection.departure_time < earliest_departure {
+                break;
+            }
+
+            let walk_arrival = final_walks.get(&connection.arrival_stop)
+                .map_or(Cost::MAX, |walk| connection.arrival_time + walk);
+            let transfer_arrival = evaluate_profile(&profiles[connection.arrival_stop], connection.arrival_time);
+            let arrival_time = walk_arrival
+                .min(trip_arrivals[connection.trip])
+                .min(transfer_arrival);
+            if arrival_time == Cost::MAX {
+                continue;
+            }
+            trip_arrivals[connection.trip] = arrival_time;
+
+            add_profile_entry(&mut profiles[connection.departure_stop], (connection.departure_time, arrival_time));
+            for &(from_stop, duration) in incoming_transfers[connection.departure_stop].iter() {
+                if duration <= connection.departure_time {
+                    add_profile_entry(&mut profiles[from_stop], (connection.departure_time - duration, arrival_time));
+                }
+            }
+        }
+
+        let mut profile: Vec<_> = profiles.swap_remove(source)
+            .into_iter()
+            .filter(|(departure_time, _)| earliest_departure <= *departure_time && *departure_time <= latest_departure)
+            .collect();
+        profile.sort();
+        profile
+    }
+}
+
+
+/// The earliest arrival of a profile when leaving at the given time.
+fn evaluate_profile(profile: &[(Cost, Cost)], time: Cost) -> Cost {
+    profile.iter()
+        .filter(|(departure_time, _)| *departure_time >= time)
+        .map(|(_, arrival_time)| *arrival_time)
+        .min()
+        .unwrap_or(Cost::MAX)
+}
+
+
+/// Add a departure and arrival pair to a profile unless an entry leaving no earlier and
+/// arriving no later is already there, removing the entries it beats.
+fn add_profile_entry(profile: &mut Vec<(Cost, Cost)>, (departure_time, arrival_time): (Cost, Cost)) {
+    if profile.iter().any(|&(other_departure, other_arrival)| {
+        other_departure >= departure_time && other_arrival <= arrival_time
+    }) {
+        return;
+    }
+    profile.retain(|&(other_departure, other_arrival)| {
+        other_departure > departure_time || other_arrival < arrival_time
+    });
+    profile.push((departure_time, arrival_time));
+}
+
+
+/// Dijkstra's algorithm on edge lengths, settling the nodes within the maximum distance.
+fn walking_distances(network: &RoadNetwork,
+                     source: NodeIndex,
+                     direction: Direction,
+                     max_distance: u64) -> HashMap<NodeIndex, u64> {
+    let mut distances = HashMap::new();
+    let mut heap = BinaryHeap::new();
+    heap.push(Reverse((0, source)));
+
+    while let Some(Reverse((distance, node_index))) = heap.pop() {
+        if distance > max_distance {
+            break;
+        }
+        if distances.contains_key(&node_index) {
+            continue;
+        }
+        distances.insert(node_index, distance);
+
+        let node = match network.get_node(node_index) {
+            Some(node) => node,
+            None => continue,
+        };
+        let edges: Vec<(NodeIndex, u64)> = match direction {
+            Direction::Forward => node.neighbours.iter().map(|edge| (edge.destination, edge.distance)).collect(),
+            Direction::Backward => node.reverse_neighbours.iter().map(|edge| (edge.origin, edge.distance)).collect(),
+        };
+        for (neighbour, edge_distance) in edges {
+            if !distances.contains_key(&neighbour) {
+                heap.push(Reverse((distance + edge_distance, neighbour)));
+            }
+        }
+    }
+
+    distances
+}
+
+
+/// The stops of a trip in order with their arrival and departure times.
+///
+/// Stops which aren't timepoints are given times spread evenly between the timed stops
+/// either side of them, and are left out if there's no timed stop before or after them.
+fn interpolate_times<'a>(stop_times: &[&'a StopTime]) -> Vec<(&'a String, Cost, Cost)> {
+    let times: Vec<_> = stop_times.iter()
+        .map(|stop_time| {
+            let arrival = stop_time.arrival_time.or(stop_time.departure_time)?;
+            Some((arrival, stop_time.departure_time.unwrap_or(arrival)))
+        })
+        .collect();
+    let timed: Vec<_> = (0..times.len()).filter(|position| times[*position].is_some()).collect();
+
+    let mut result = Vec::new();
+    for (index, position) in timed.iter().enumerate() {
+        let (arrival, departure) = times[*position].unwrap();
+        result.push((&stop_times[*position].stop_id, arrival, departure));
+
+        if let Some(next) = timed.get(index + 1) {
+            let next_arrival = times[*next].unwrap().0.max(departure);
+            let steps = (next - position) as Cost;
+            for (step, stop_time) in stop_times[position + 1..*next].iter().enumerate() {
+                let time = departure + (next_arrival - departure) * (step + 1) as Cost / steps;
+                result.push((&stop_time.stop_id, time, time));
+            }
+        }
+    }
+    result
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::transit::*;
+    use crate::gtfs::read_gtfs;
+    use crate::test_utils::build_grid_network;
+
+    use std::path::Path;
+
+
+    fn time(hours: Cost, minutes: Cost) -> Cost {
+        hours * 3600 + minutes * 60
+    }
+
+
+    fn get_test_timetable(date: ServiceDate) -> Timetable {
+        let path = Path::new("tests").join("fixtures").join("gtfs");
+        let feed = read_gtfs(path.to_str().unwrap()).unwrap();
+        Timetable::new(&feed, date).unwrap()
+    }
+
+
+    #[test]
+    fn test_timetable() {
+        let timetable = get_test_timetable(20240102);
+        assert_eq!(7, timetable.connections().len());
+        assert_eq!(Some(2), timetable.stop_index("C"));
+        assert!(timetable.connections().windows(2).all(|pair| pair[0].departure_time <= pair[1].departure_time));
+
+        // Not running on a Saturday, or on a date removed by an exception, leaving only
+        // the late trip of the day before after midnight.
+        for date in [20240106, 20240103] {
+            let connections = get_test_timetable(date).connections().to_vec();
+            assert_eq!(1, connections.len());
+            assert_eq!((time(0, 50), time(1, 0)), (connections[0].departure_time, connections[0].arrival_time));
+        }
+        assert!(get_test_timetable(20240107).connections().is_empty());
+    }
+
+
+    #[test]
+    fn test_untimed_stops() {
+        let timetable = get_test_timetable(20240102);
+        let (a, b) = (0, 1);
+        assert_eq!(time(8, 10), timetable.earliest_arrival(a, b, time(7, 55)).unwrap().arrival_time());
+
+        let stop_time = |stop_id: &str, time: Option<Cost>| StopTime {
+            trip_id: "t".to_string(),
+            arrival_time: time,
+            departure_time: time,
+            stop_id: stop_id.to_string(),
+            stop_sequence: 0,
+        };
+        let stop_times = [stop_time("A", None), stop_time("B", Some(100)), stop_time("C", None),
+                          stop_time("D", None), stop_time("E", Some(160)), stop_time("F", None)];
+        let stop_times: Vec<_> = stop_times.iter().collect();
+        let times: Vec<_> = interpolate_times(&stop_times).into_iter()
+            .map(|(stop_id, arrival, _)| (stop_id.as_str(), arrival))
+            .collect();
+        assert_eq!(vec![("B", 100), ("C", 120), ("D", 140), ("E", 160)], times);
+    }
+
+
+    #[test]
+    fn test_trips_from_the_day_before() {
+        // The late trip leaving at 24:50 on Tuesday runs on into Wednesday, when the
+        // service is cancelled.
+        let timetable = get_test_timetable(20240103);
+        let (b, c) = (1, 2);
+        let journey = timetable.earliest_arrival(b, c, time(0, 30)).unwrap();
+        assert_eq!(time(1, 0), journey.arrival_time());
+        assert!(timetable.earliest_arrival(b, c, time(0, 51)).is_none());
+    }
+
+
+    #[test]
+    fn test_earliest_arrival() {
+        let timetable = get_test_timetable(20240102);
+        let (a, b, c) = (0, 1, 2);
+
+        let journey = timetable.earliest_arrival(a, c, time(8, 0)).unwrap();
+        assert_eq!(time(8, 12), journey.arrival_time());
+        assert_eq!(&[Leg::Ride {
+            trip_id: "fast".to_string(),
+            from_stop: a,
+            to_stop: c,
+            departure_time: time(8, 5),
+            arrival_time: time(8, 12),
+        }], journey.legs());
+
+        assert_eq!(time(7, 50), timetable.earliest_arrival(a, c, time(7, 0)).unwrap().arrival_time());
+        assert_eq!(time(25, 0), timetable.earliest_arrival(b, c, time(24, 0)).unwrap().arrival_time());
+        assert!(timetable.earliest_arrival(a, c, time(9, 0)).is_none());
+        assert!(timetable.earliest_arrival(c, a, time(7, 0)).is_none());
+    }
+
+
+    #[test]
+    fn test_earliest_arrival_with_transfer() {
+        let mut timetable = get_test_timetable(20240102);
+        let (a, c, d, e) = (0, 2, 3, 4);
+        assert!(timetable.earliest_arrival(a, d, time(8, 0)).is_none());
+
+        timetable.add_transfer(c, e, 120);
+        let journey = timetable.earliest_arrival(a, d, time(8, 0)).unwrap();
+        assert_eq!(time(8, 30), journey.arrival_time());
+        assert_eq!(3, journey.legs().len());
+        assert_eq!(Leg::Walk {
+            from_stop: Some(c),
+            to_stop: Some(e),
+            departure_time: time(8, 12),
+            arrival_time: time(8, 14),
+        }, journey.legs()[1]);
+    }
+
+
+    #[test]
+    fn test_profile() {
+        let mut timetable = get_test_timetable(20240102);
+        let (a, c, d, e) = (0, 2, 3, 4);
+
+        assert_eq!(vec![(time(7, 30), time(7, 50)), (time(8, 5), time(8, 12))],
+                   timetable.profile(a, c, time(7, 0), time(9, 0)));
+        assert_eq!(vec![(time(8, 5), time(8, 12))], timetable.profile(a, c, time(7, 31), time(9, 0)));
+
+        timetable.add_transfer(c, e, 120);
+        assert_eq!(vec![(time(8, 5), time(8, 30))], timetable.profile(a, d, time(7, 0), time(9, 0)));
+    }
+
+
+    #[test]
+    fn test_link_to_road_network() {
+        let network = build_grid_network(5, 5);
+        let mut timetable = get_test_timetable(20240102);
+        assert!(timetable.earliest_arrival_between_nodes(&network, 1, 20, time(7, 55)).is_none());
+
+        // Short enough that walking from Marktplatz to node 20 isn't quicker than the feeder.
+        timetable.link_to_road_network(&network, WalkingParameters {speed_ms: 1.4, max_duration: 240});
+        let (a, c, d, e) = (0, 2, 3, 4);
+        assert_eq!(Some(0), timetable.stop_node(a));
+        assert_eq!(Some(24), timetable.stop_node(c));
+        assert_eq!(Some(24), timetable.stop_node(e));
+
+        let journey = timetable.earliest_arrival_between_nodes(&network, 1, 20, time(7, 55)).unwrap();
+        assert_eq!(time(8, 30), journey.arrival_time());
+        let legs = journey.legs();
+        assert_eq!(4, legs.len());
+        assert!(matches!(legs[0], Leg::Walk {from_stop: None, to_stop: Some(stop), ..} if stop == a));
+        assert!(matches!(legs[1], Leg::Ride {from_stop, to_stop, ..} if from_stop == a && to_stop == c));
+        assert!(matches!(legs[2], Leg::Walk {from_stop: Some(from), to_stop: Some(to), ..} if from == c && to == e));
+        assert!(matches!(legs[3], Leg::Ride {to_stop, ..} if to_stop == d));
+
+        // Close enough to walk the whole way.
+        let journey = timetable.earliest_arrival_between_nodes(&network, 1, 2, time(7, 55)).unwrap();
+        assert!(matches!(journey.legs(), [Leg::Walk {from_stop: None, to_stop: None, ..}]));
+    }
+}
@@ -0,0 +1,151 @@
+use crate::geo_utils::{bearing, Location};
+use crate::road_network::{Cost, Node};
+
+
+/// The kind of turn made at a junction, for traffic driving on the right.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TurnType {
+    Straight,
+    Right,
+    Left,
+    UTurn,
+}
+
+
+/// Penalties in seconds added to a route for each turn it makes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TurnCostModel {
+    pub straight_cost: Cost,
+    pub right_cost: Cost,
+    pub left_cost: Cost,
+    pub u_turn_cost: Cost,
+    /// Forbid U-turns, except at dead ends where there's no other way to go.
+    pub forbid_u_turns: bool,
+    /// Turns of up to this many degrees either way count as going straight on.
+    pub straight_angle: f64,
+    /// Turns of at least this many degrees either way count as U-turns.
+    pub u_turn_angle: f64,
+}
+
+
+impl Default for TurnCostModel {
+    fn default() -> Self {
+        TurnCostModel {
+            straight_cost: 0,
+            right_cost: 5,
+            left_cost: 15,
+            u_turn_cost: 40,
+            forbid_u_turns: false,
+            straight_angle: 30.,
+            u_turn_angle: 150.,
+        }
+    }
+}
+
+
+impl TurnCostModel {
+
+    /// Every turn is free, as if there were no turn costs at all.
+    pub fn free() -> Self {
+        TurnCostModel {
+            straight_cost: 0,
+            right_cost: 0,
+            left_cost: 0,
+            u_turn_cost: 0,
+            ..TurnCostModel::default()
+        }
+    }
+
+
+    /// The kind of turn from the edge `from` to `via` onto the edge `via` to `to`.
+    ///
+    /// Returning to the node just left is always a U-turn, whatever the geometry.
+    pub fn turn_type(&self, from: &Node, via: &Node, to: &Node) -> TurnType {
+        if from.id == to.id {
+            return TurnType::UTurn;
+        }
+
+        let angle = turn_angle(&from.location, &via.location, &to.location);
+        if angle.abs() <= self.straight_angle {
+            TurnType::Straight
+        } else if angle.abs() >= self.u_turn_angle {
+            TurnType::UTurn
+        } else if angle > 0. {
+            TurnType::Right
+        } else {
+            TurnType::Left
+        }
+    }
+
+
+    /// The penalty for turning from `from` through `via` to `to`, or None if the turn is forbidden.
+    pub fn turn_cost(&self, from: &Node, via: &Node, to: &Node) -> Option<Cost> {
+        match self.turn_type(from, via, to) {
+            TurnType::Straight => Some(self.straight_cost),
+            TurnType::Right => Some(self.right_cost),
+            TurnType::Left => Some(self.left_cost),
+            TurnType::UTurn => {
+                let is_dead_end = via.neighbours.iter().all(|edge| edge.destination == from.id);
+                if self.forbid_u_turns && !is_dead_end {
+                    None
+                } else {
+                    Some(self.u_turn_cost)
+                }
+            },
+        }
+    }
+}
+
+
+/// The change in heading in degrees when passing through `via`, positive turning clockwise.
+fn turn_angle(from: &Location, via: &Location, to: &Location) -> f64 {
+    let change = bearing(via, to) - bearing(from, via);
+    if change > 180. {
+        change - 360.
+    } else if change <= -180. {
+        change + 360.
+    } else {
+        change
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::turn_costs::*;
+    use crate::road_network::RoadNetwork;
+    use crate::test_utils::build_grid_network;
+
+
+    fn node(network: &RoadNetwork, node_index: u64) -> &Node {
+        network.get_node(node_index).unwrap()
+    }
+
+
+    #[test]
+    fn test_turn_types() {
+        // Rows run north and columns east, so heading east from 3 to 4 then turning
+        // north onto 7 is a left turn and south onto 1 a right turn.
+        let network = build_grid_network(3, 3);
+        let model = TurnCostModel::default();
+        let turn = |from, via, to| model.turn_type(node(&network, from), node(&network, via), node(&network, to));
+
+        assert_eq!(TurnType::Straight, turn(3, 4, 5));
+        assert_eq!(TurnType::Left, turn(3, 4, 7));
+        assert_eq!(TurnType::Right, turn(3, 4, 1));
+        assert_eq!(TurnType::UTurn, turn(3, 4, 3));
+        assert_eq!(Some(15), model.turn_cost(node(&network, 3), node(&network, 4), node(&network, 7)));
+    }
+
+
+    #[test]
+    fn test_forbidden_u_turns() {
+        let network = build_grid_network(1, 3);
+        let model = TurnCostModel {forbid_u_turns: true, ..TurnCostModel::default()};
+
+        assert_eq!(None, model.turn_cost(node(&network, 0), node(&network, 1), node(&network, 0)));
+        // Node 2 is a dead end, so turning around there is allowed.
+        assert_eq!(Some(40), model.turn_cost(node(&network, 1), node(&network, 2), node(&network, 1)));
+        assert_eq!(Some(0), TurnCostModel::free().turn_cost(node(&network, 0), node(&network, 1), node(&network, 0)));
+    }
+}
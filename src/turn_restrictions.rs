@@ -0,0 +1,490 @@
+use crate::osm_reader::{Osm, OsmRelation, OsmWay};
+use crate::road_network::{Cost, RoadNetwork, Node, NodeIndex};
+use crate::shortest_path::{SearchStatistics, ShortestPath};
+use crate::turn_costs::TurnCostModel;
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::time::Instant;
+
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RestrictionKind {
+    /// Routes may not follow the nodes of the restriction.
+    No,
+    /// Routes following all but the last node of the restriction must continue to the last.
+    Only,
+}
+
+
+/// A restriction on the sequence of nodes a route may follow through a junction.
+///
+/// The nodes run from the last node of the from way before the junction, through the
+/// via node or via ways, to the first node of the to way after it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TurnRestriction {
+    pub kind: RestrictionKind,
+    pub nodes: Vec<NodeIndex>,
+}
+
+
+/// The turn restrictions of every restriction relation in the OSM data.
+///
+/// Relations which are malformed, or whose ways don't meet where the relation says
+/// they do, are skipped.
+pub fn turn_restrictions_from_osm(osm: &Osm) -> Vec<TurnRestriction> {
+    let ways: HashMap<_, _> = osm.ways.iter().map(|way| (way.id, way)).collect();
+    osm.relations.iter()
+        .flat_map(|relation| relation_to_restrictions(relation, &ways))
+        .collect()
+}
+
+
+fn relation_to_restrictions(relation: &OsmRelation, ways: &HashMap<u64, &OsmWay>) -> Vec<TurnRestriction> {
+    let kind = match relation.restriction() {
+        Some(restriction) if restriction.starts_with("no_") => RestrictionKind::No,
+        Some(restriction) if restriction.starts_with("only_") => RestrictionKind::Only,
+        _ => return Vec::new(),
+    };
+
+    let way = |role| match relation.member_refs(role, "way").as_slice() {
+        [way_id] => ways.get(way_id).map(|way| way_nodes(way)),
+        _ => None,
+    };
+    let (from_way, to_way) = match (way("from"), way("to")) {
+        (Some(from_way), Some(to_way)) => (from_way, to_way),
+        _ => return Vec::new(),
+    };
+
+    let via_nodes = match relation.member_refs("via", "node").as_slice() {
+        [via_node] => vec![*via_node],
+        [] => {
+            let via_ways: Option<Vec<_>> = relation.member_refs("via", "way")
+                .iter()
+                .map(|way_id| ways.get(way_id).map(|way| way_nodes(way)))
+                .collect();
+            match via_ways.and_then(|via_ways| chain_via_ways(&from_way, &via_ways)) {
+                Some(via_nodes) => via_nodes,
+                None => return Vec::new(),
+            }
+        },
+        _ => return Vec::new(),
+    };
+
+    let first_via = via_nodes[0];
+    let last_via = via_nodes[via_nodes.len() - 1];
+    let mut restrictions = Vec::new();
+    for from_node in adjacent_nodes(&from_way, first_via) {
+        for to_node in adjacent_nodes(&to_way, last_via) {
+            let mut nodes = vec![from_node];
+            nodes.extend_from_slice(&via_nodes);
+            nodes.push(to_node);
+            restrictions.push(TurnRestriction {kind, nodes});
+        }
+    }
+    restrictions
+}
+
+
+fn way_nodes(way: &OsmWay) -> Vec<NodeIndex> {
+    way.nodes.iter().map(|nd| nd.ref_).collect()
+}
+
+
+/// The nodes next to a node along a way.
+fn adjacent_nodes(way: &[NodeIndex], node: NodeIndex) -> Vec<NodeIndex> {
+    let mut adjacent = Vec::new();
+    for (index, way_node) in way.iter().enumerate() {
+        if *way_node != node {
+            continue;
+        }
+        if index > 0 {
+            adjacent.push(way[index - 1]);
+        }
+        if index + 1 < way.len() {
+            adjacent.push(way[index + 1]);
+        }
+    }
+    adjacent
+}
+
+
+/// The nodes along the via ways, starting where the first meets an end of the from way.
+fn chain_via_ways(from_way: &[NodeIndex], via_ways: &[Vec<NodeIndex>]) -> Option<Vec<NodeIndex>> {
+    let first_way = via_ways.first()?;
+    let from_ends = [*from_way.first()?, *from_way.last()?];
+
+    let mut chain: Vec<NodeIndex> = Vec::new();
+    for way in via_ways.iter() {
+        let start = match chain.last() {
+            Some(end) => *end,
+            None => *[*first_way.first()?, *first_way.last()?]
+                .iter()
+                .find(|end| from_ends.contains(end))?,
+        };
+
+        let mut way = way.clone();
+        if way.last() == Some(&start) {
+            way.reverse();
+        }
+        if way.first() != Some(&start) {
+            return None;
+        }
+        let skip = if chain.is_empty() { 0 } else { 1 };
+        chain.extend(way.into_iter().skip(skip));
+    }
+    Some(chain)
+}
+
+
+/// Tracks how much of each restriction the end of a route matches.
+///
+/// The state of a route is the longest run of nodes at its end which starts some
+/// restriction, and at least the last two nodes so the edge it arrived on is known.
+/// Following an edge then only needs the state and the next node.
+struct TurnAutomaton {
+    prefixes: HashSet<Vec<NodeIndex>>,
+    forbidden: HashSet<Vec<NodeIndex>>,
+    only: HashMap<Vec<NodeIndex>, Vec<NodeIndex>>,
+}
+
+
+impl TurnAutomaton {
+
+    fn new(restrictions: &[TurnRestriction]) -> Self {
+        let mut prefixes = HashSet::new();
+        let mut forbidden = HashSet::new();
+        let mut only: HashMap<_, Vec<_>> = HashMap::new();
+
+        for restriction in restrictions.iter().filter(|restriction| restriction.nodes.len() >= 2) {
+            let nodes = &restriction.nodes;
+            for length in 2..nodes.len() {
+                prefixes.insert(nodes[..length].to_vec());
+            }
+            match restriction.kind {
+                RestrictionKind::No => {
+                    forbidden.insert(nodes.clone());
+                },
+                RestrictionKind::Only => {
+                    // Every via node of the restriction must be followed by its next node.
+                    for length in 2..nodes.len() {
+                        only.entry(nodes[..length].to_vec())
+                            .or_default()
+                            .push(nodes[length]);
+                    }
+                },
+            }
+        }
+
+        TurnAutomaton {prefixes, forbidden, only}
+    }
+
+
+    /// The state after moving on to the next node, or None if the turn is forbidden.
+    fn next_state(&self, state: &[NodeIndex], next: NodeIndex) -> Option<Vec<NodeIndex>> {
+        let mut nodes = state.to_vec();
+        nodes.push(next);
+
+        if (0..nodes.len()).any(|start| self.forbidden.contains(&nodes[start..])) {
+            return None;
+        }
+
+        let mut required = (0..state.len())
+            .filter_map(|start| self.only.get(&state[start..]))
+            .flatten()
+            .peekable();
+        if required.peek().is_some() && !required.any(|node| *node == next) {
+            return None;
+        }
+
+        let start = (0..nodes.len())
+            .find(|start| self.prefixes.contains(&nodes[*start..]))
+            .unwrap_or(nodes.len() - 1)
+            .min(nodes.len() - 2);
+        Some(nodes.split_off(start))
+    }
+}
+
+
+/// Dijkstra's algorithm which never makes a turn forbidden by the network's restrictions.
+///
+/// The search runs over both the edge arrived on and how much of any restriction has
+/// just been followed, so a route may pass through the same node more than once, for
+/// example to go around the block instead of turning left.
+pub fn turn_aware_shortest_path(network: &RoadNetwork,
+                                start_node: &Node,
+                                end_node: &Node,
+) -> Option<ShortestPath> {
+    turn_cost_shortest_path(network, start_node, end_node, &TurnCostModel::free())
+}
+
+
+/// Like `turn_aware_shortest_path`, also adding the model's penalty for every turn made.
+///
+/// The cost of the returned path includes the turn penalties.
+pub fn turn_cost_shortest_path(network: &RoadNetwork,
+                               start_node: &Node,
+                               end_node: &Node,
+                               turn_costs: &TurnCostModel,
+) -> Option<ShortestPath> {
+    let start_time = Instant::now();
+    let mut statistics = SearchStatistics::default();
+    let automaton = TurnAutomaton::new(network.turn_restrictions());
+
+    let mut states = vec![vec![start_node.id]];
+    let mut state_indices = HashMap::new();
+    let mut costs: Vec<Cost> = vec![0];
+    let mut parents: Vec<Option<usize>> = vec![None];
+    let mut settled = HashSet::new();
+    let mut heap = BinaryHeap::new();
+
+    state_indices.insert(states[0].clone(), 0);
+    heap.push(Reverse((0, 0)));
+    statistics.record_push(heap.len());
+
+    while let Some(Reverse((cost, state_index))) = heap.pop() {
+        if !settled.insert(state_index) {
+            continue;
+        }
+        statistics.settled_nodes += 1;
+
+        let node_index = *states[state_index].last().unwrap();
+        if node_index == end_node.id {
+            let mut path = Vec::new();
+            let mut current = Some(state_index);
+            while let Some(index) = current {
+                path.push(*states[index].last().unwrap());
+                current = parents[index];
+            }
+            path.reverse();
+
+            statistics.duration = start_time.elapsed();
+            return Some(ShortestPath {cost, path, statistics: Some(statistics)});
+        }
+
+        let node = network.get_node(node_index).unwrap();
+        for edge in node.neighbours.iter() {
+            let next_state = match automaton.next_state(&states[state_index], edge.destination) {
+                Some(next_state) => next_state,
+                None => continue,
+            };
+            let turn_cost = match states[state_index].iter().rev().nth(1) {
+                Some(previous_index) => {
+                    let previous = network.get_node(*previous_index).unwrap();
+                    let next = network.get_node(edge.destination).unwrap();
+                    match turn_costs.turn_cost(previous, node, next) {
+                        Some(turn_cost) => turn_cost,
+                        None => continue,
+                    }
+                },
+                None => 0,
+            };
+            statistics.relaxed_edges += 1;
+
+            let next_index = *state_indices.entry(next_state.clone()).or_insert_with(|| {
+                states.push(next_state);
+                costs.push(Cost::MAX);
+                parents.push(None);
+                states.len() - 1
+            });
+
+            let next_cost = cost + edge.cost + turn_cost;
+            if next_cost < costs[next_index] {
+                costs[next_index] = next_cost;
+                parents[next_index] = Some(state_index);
+                heap.push(Reverse((next_cost, next_index)));
+                statistics.record_push(heap.len());
+            }
+        }
+    }
+
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::turn_restrictions::*;
+    use crate::geo_utils::Location;
+    use crate::road_network_builder::build_road_network_from_osm;
+    use crate::shortest_path::dijkstra_shortest_path;
+    use crate::test_utils::build_grid_network;
+
+    use serde_xml_rs::deserialize;
+
+
+    fn get_test_network() -> RoadNetwork {
+        let mut network = RoadNetwork::new();
+        for i in 1..6 {
+            network.add_node(Node::new(i, Location::new(0., 0.))).unwrap();
+        }
+
+        // Straight on from 1 through 2 to 3, or around the block through 4.
+        network.add_edge(1, 2, 10);
+        network.add_edge(2, 3, 10);
+        network.add_edge(2, 4, 10);
+        network.add_edge(4, 2, 10);
+        network.add_edge(4, 3, 30);
+
+        network
+    }
+
+
+    fn route(network: &RoadNetwork, start: NodeIndex, end: NodeIndex) -> Option<ShortestPath> {
+        turn_aware_shortest_path(network, network.get_node(start).unwrap(), network.get_node(end).unwrap())
+    }
+
+
+    #[test]
+    fn test_no_restrictions_matches_dijkstra() {
+        let network = get_test_network();
+        let start = network.get_node(1).unwrap();
+        let end = network.get_node(3).unwrap();
+        assert_eq!(dijkstra_shortest_path(&network, start, end), route(&network, 1, 3));
+        assert!(route(&network, 3, 1).is_none());
+    }
+
+
+    #[test]
+    fn test_no_turn_via_node() {
+        let mut network = get_test_network();
+        network.add_turn_restriction(TurnRestriction {kind: RestrictionKind::No, nodes: vec![1, 2, 3]});
+
+        let result = route(&network, 1, 3).unwrap();
+        assert_eq!(&[1, 2, 4, 2, 3], result.path());
+        assert_eq!(40, result.cost());
+
+        // Only routes arriving from 1 are restricted.
+        assert_eq!(&[4, 2, 3], route(&network, 4, 3).unwrap().path());
+    }
+
+
+    #[test]
+    fn test_only_turn() {
+        let mut network = get_test_network();
+        network.add_turn_restriction(TurnRestriction {kind: RestrictionKind::Only, nodes: vec![1, 2, 4]});
+        assert_eq!(&[1, 2, 4, 2, 3], route(&network, 1, 3).unwrap().path());
+    }
+
+
+    #[test]
+    fn test_only_turn_via_way() {
+        let mut network = get_test_network();
+        network.add_edge(4, 5, 1);
+        network.add_edge(5, 3, 50);
+        network.add_turn_restriction(TurnRestriction {kind: RestrictionKind::Only, nodes: vec![1, 2, 4, 5]});
+
+        let result = route(&network, 1, 3).unwrap();
+        assert_eq!(&[1, 2, 4, 5, 3], result.path());
+        assert_eq!(71, result.cost());
+        assert_eq!(&[2, 3], route(&network, 2, 3).unwrap().path());
+    }
+
+
+    #[test]
+    fn test_no_turn_via_way() {
+        let mut network = get_test_network();
+        network.add_edge(4, 5, 1);
+        network.add_turn_restriction(TurnRestriction {kind: RestrictionKind::No, nodes: vec![1, 2, 4, 5]});
+
+        assert_eq!(&[1, 2, 4, 2, 4, 5], route(&network, 1, 5).unwrap().path());
+        assert_eq!(&[2, 4, 5], route(&network, 2, 5).unwrap().path());
+    }
+
+
+    #[test]
+    fn test_turn_costs_avoid_zig_zags() {
+        // Every path from one corner of the inner residential grid to the other has the
+        // same length, but the one turning only once should be preferred.
+        let network = build_grid_network(5, 5);
+        let start = network.get_node(6).unwrap();
+        let end = network.get_node(18).unwrap();
+        let result = turn_cost_shortest_path(&network, start, end, &TurnCostModel::default()).unwrap();
+
+        let straight_cost = dijkstra_shortest_path(&network, start, end).unwrap().cost();
+        assert_eq!(5, result.path().len());
+        assert!(result.path() == [6, 7, 8, 13, 18] || result.path() == [6, 11, 16, 17, 18]);
+        assert!(result.cost() <= straight_cost + 15);
+    }
+
+
+    #[test]
+    fn test_forbidden_u_turns() {
+        let mut network = get_test_network();
+        network.add_turn_restriction(TurnRestriction {kind: RestrictionKind::No, nodes: vec![1, 2, 3]});
+        let model = TurnCostModel {forbid_u_turns: true, ..TurnCostModel::free()};
+
+        let start = network.get_node(1).unwrap();
+        let end = network.get_node(3).unwrap();
+        let result = turn_cost_shortest_path(&network, start, end, &model).unwrap();
+        assert_eq!(&[1, 2, 4, 3], result.path());
+        assert_eq!(50, result.cost());
+    }
+
+
+    #[test]
+    fn test_restrictions_from_osm() {
+        let osm: Osm = deserialize(r##"
+        <osm>
+            <node id="1" lat="49.0" lon="7.0"/>
+            <node id="2" lat="49.001" lon="7.0"/>
+            <node id="3" lat="49.002" lon="7.0"/>
+            <node id="4" lat="49.001" lon="7.001"/>
+            <way id="10">
+                <nd ref="1"/>
+                <nd ref="2"/>
+                <tag k="highway" v="residential"/>
+            </way>
+            <way id="11">
+                <nd ref="2"/>
+                <nd ref="3"/>
+                <tag k="highway" v="residential"/>
+            </way>
+            <way id="12">
+                <nd ref="4"/>
+                <nd ref="2"/>
+                <tag k="highway" v="residential"/>
+            </way>
+            <way id="13">
+                <nd ref="4"/>
+                <nd ref="3"/>
+                <tag k="highway" v="residential"/>
+            </way>
+            <relation id="20">
+                <member type="way" ref="10" role="from"/>
+                <member type="node" ref="2" role="via"/>
+                <member type="way" ref="11" role="to"/>
+                <tag k="restriction" v="no_straight_on"/>
+            </relation>
+            <relation id="21">
+                <member type="way" ref="10" role="from"/>
+                <member type="way" ref="12" role="via"/>
+                <member type="way" ref="13" role="to"/>
+                <tag k="restriction" v="only_left_turn"/>
+            </relation>
+            <relation id="22">
+                <member type="way" ref="99" role="from"/>
+                <member type="node" ref="2" role="via"/>
+                <member type="way" ref="11" role="to"/>
+                <tag k="restriction" v="no_left_turn"/>
+            </relation>
+            <relation id="23">
+                <member type="way" ref="10" role="from"/>
+                <member type="way" ref="13" role="via"/>
+                <member type="way" ref="11" role="to"/>
+                <tag k="restriction" v="no_right_turn"/>
+            </relation>
+        </osm>
+        "##.as_bytes()).unwrap();
+
+        let restrictions = turn_restrictions_from_osm(&osm);
+        assert_eq!(vec![
+            TurnRestriction {kind: RestrictionKind::No, nodes: vec![1, 2, 3]},
+            TurnRestriction {kind: RestrictionKind::Only, nodes: vec![1, 2, 4, 3]},
+        ], restrictions);
+
+        let network = build_road_network_from_osm(osm).unwrap();
+        assert_eq!(restrictions.as_slice(), network.turn_restrictions());
+        assert_eq!(&[1, 2, 4, 3], route(&network, 1, 3).unwrap().path());
+    }
+}
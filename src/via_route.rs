@@ -0,0 +1,128 @@
+use crate::road_network::{Cost, RoadNetwork, NodeIndex};
+use crate::shortest_path::{dijkstra_shortest_path, SearchStatistics, ShortestPath};
+
+
+/// A route through a list of waypoints, made up of one leg between each consecutive pair.
+#[derive(Debug)]
+pub struct ViaRoute {
+    route: ShortestPath,
+    leg_costs: Vec<Cost>,
+    waypoint_positions: Vec<usize>,
+}
+
+
+impl ViaRoute {
+
+    /// The whole route from the first waypoint to the last.
+    pub fn route(&self) -> &ShortestPath {
+        &self.route
+    }
+
+
+    pub fn leg_costs(&self) -> &[Cost] {
+        &self.leg_costs
+    }
+
+
+    /// The index in the route's path of each waypoint, so leg `i` runs from
+    /// `waypoint_positions()[i]` to `waypoint_positions()[i + 1]`.
+    pub fn waypoint_positions(&self) -> &[usize] {
+        &self.waypoint_positions
+    }
+
+
+    /// The nodes of a single leg, including the waypoints at either end.
+    pub fn leg_path(&self, leg_index: usize) -> &[NodeIndex] {
+        let start = self.waypoint_positions[leg_index];
+        let end = self.waypoint_positions[leg_index + 1];
+        &self.route.path[start..=end]
+    }
+}
+
+
+/// The optimal route visiting each of the waypoints in order.
+///
+/// Each leg is routed independently and the legs stitched together. Returns None if
+/// there are no waypoints or any leg has no path.
+pub fn route_via(network: &RoadNetwork, waypoints: &[NodeIndex]) -> Option<ViaRoute> {
+    let first = network.get_node(*waypoints.first()?)?;
+
+    let mut path = vec![first.id];
+    let mut leg_costs = Vec::with_capacity(waypoints.len() - 1);
+    let mut waypoint_positions = vec![0];
+    let mut statistics = SearchStatistics::default();
+
+    for (from, to) in waypoints.iter().zip(waypoints.iter().skip(1)) {
+        let leg = dijkstra_shortest_path(network, network.get_node(*from)?, network.get_node(*to)?)?;
+
+        if let Some(leg_statistics) = leg.statistics() {
+            statistics.add(leg_statistics);
+        }
+        leg_costs.push(leg.cost);
+        path.extend(leg.path.into_iter().skip(1));
+        waypoint_positions.push(path.len() - 1);
+    }
+
+    Some(ViaRoute {
+        route: ShortestPath {cost: leg_costs.iter().sum(), path, statistics: Some(statistics)},
+        leg_costs,
+        waypoint_positions,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::via_route::*;
+    use crate::geo_utils::Location;
+    use crate::road_network::Node;
+
+
+    fn get_test_network() -> RoadNetwork {
+        let mut network = RoadNetwork::new();
+        for i in 1..6 {
+            network.add_node(Node::new(i, Location::new(0., 0.))).unwrap();
+        }
+
+        network.add_edge(1, 2, 5);
+        network.add_edge(2, 3, 10);
+        network.add_edge(3, 4, 20);
+        network.add_edge(1, 4, 100);
+        network.add_edge(4, 1, 1);
+
+        network
+    }
+
+
+    #[test]
+    fn test_route_via_waypoints() {
+        let network = get_test_network();
+        let route = route_via(&network, &[1, 3, 1, 2]).unwrap();
+
+        assert_eq!(&[1, 2, 3, 4, 1, 2], route.route().path());
+        assert_eq!(&[15, 21, 5], route.leg_costs());
+        assert_eq!(41, route.route().cost());
+        assert_eq!(&[0, 2, 4, 5], route.waypoint_positions());
+        assert_eq!(&[3, 4, 1], route.leg_path(1));
+    }
+
+
+    #[test]
+    fn test_single_waypoint() {
+        let network = get_test_network();
+        let route = route_via(&network, &[2]).unwrap();
+
+        assert_eq!(&[2], route.route().path());
+        assert_eq!(0, route.route().cost());
+        assert!(route.leg_costs().is_empty());
+    }
+
+
+    #[test]
+    fn test_unroutable_waypoints() {
+        let network = get_test_network();
+        assert!(route_via(&network, &[]).is_none());
+        assert!(route_via(&network, &[1, 5]).is_none());
+        assert!(route_via(&network, &[1, 99]).is_none());
+    }
+}